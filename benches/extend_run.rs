@@ -8,7 +8,7 @@ fn benchmark_extend_run_1(c: &mut Criterion) {
 
     for size in [10usize, 100, 1000, 10000, 100000, 200000, 300000].into_iter() {
         // Create a vector of 0 to size - 1
-        let v = (0i32..size as i32).into_iter().collect::<Vec<_>>();
+        let v = (0i32..size as i32).collect::<Vec<_>>();
         let mut v_reverse = v.clone();
         v_reverse.reverse();
 
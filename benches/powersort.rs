@@ -1,6 +1,6 @@
 use criterion::*;
 use powersort::{powersort_alternatives};
-use powersort::sequences::{generate_runs_with_average_length, generate_random_sequence, generate_timsort_drag};
+use powersort::sequences::{generate_runs_with_average_length, generate_random_sequence};
 use powersort::powersort::power_sort;
 
 fn benchmark_powersort_1(c: &mut Criterion) {
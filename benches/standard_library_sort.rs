@@ -103,6 +103,7 @@ where
     merge_cost
 }
 
+#[allow(dead_code)]
 fn benchmark_standardsort_1(c: &mut Criterion) {
     let mut is_less = |a: &i32, b: &i32| a < b;
     let mut group = c.benchmark_group("standard_powersort_runs");
@@ -160,6 +161,7 @@ fn benchmark_standardsort_2(c: &mut Criterion) {
     group.finish();
 }
 
+#[allow(dead_code)]
 fn benchmark_standardsort_3(c: &mut Criterion) {
     let mut is_less = |a: &i32, b: &i32| a < b;
     let mut group = c.benchmark_group("standard_powersort_drag");
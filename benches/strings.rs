@@ -0,0 +1,59 @@
+use criterion::*;
+use powersort::powersort_final::power_sort;
+use powersort::sequences::{generate_paths, generate_urls};
+use powersort::strings::power_sort_strings;
+
+fn benchmark_strings_1(c: &mut Criterion) {
+    let mut group = c.benchmark_group("strings_urls");
+    for size in [1000usize, 10000, 100000].into_iter() {
+        let sequence = generate_urls(size);
+
+        group.bench_function(BenchmarkId::new("lcp_powersort", size), |b| {
+            b.iter_batched(|| {
+                sequence.clone()
+            }, |mut v: Vec<String>| {
+                power_sort_strings(&mut v);
+            }
+            , BatchSize::SmallInput);
+        });
+
+        group.bench_function(BenchmarkId::new("final_powersort", size), |b| {
+            b.iter_batched(|| {
+                sequence.clone()
+            }, |mut v: Vec<String>| {
+                power_sort(&mut v, |a, b| a < b);
+            }
+            , BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+fn benchmark_strings_2(c: &mut Criterion) {
+    let mut group = c.benchmark_group("strings_paths");
+    for size in [1000usize, 10000, 100000].into_iter() {
+        let sequence = generate_paths(size);
+
+        group.bench_function(BenchmarkId::new("lcp_powersort", size), |b| {
+            b.iter_batched(|| {
+                sequence.clone()
+            }, |mut v: Vec<String>| {
+                power_sort_strings(&mut v);
+            }
+            , BatchSize::SmallInput);
+        });
+
+        group.bench_function(BenchmarkId::new("final_powersort", size), |b| {
+            b.iter_batched(|| {
+                sequence.clone()
+            }, |mut v: Vec<String>| {
+                power_sort(&mut v, |a, b| a < b);
+            }
+            , BatchSize::SmallInput);
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(strings_group, benchmark_strings_1, benchmark_strings_2);
+criterion_main!(strings_group);
@@ -19,7 +19,7 @@ where
             if is_less(&v[i], &first) {
                 // Shift v[1..i] to v[0..i-1] by copying
                 unsafe {
-                    ptr::copy_nonoverlapping(
+                    ptr::copy(
                         v.get_unchecked(1),
                         v.get_unchecked_mut(0),
                         i,
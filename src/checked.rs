@@ -0,0 +1,224 @@
+use std::fmt;
+use std::mem::size_of;
+
+use crate::powersort_final::{power_sort_hooked, Pending, SortHooks};
+
+/// The ordering invariant that `is_less` was caught breaking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// `is_less(a, a)` returned true.
+    NotIrreflexive,
+    /// Both `is_less(a, b)` and `is_less(b, a)` returned true.
+    NotAsymmetric,
+    /// `a` and `b` are equivalent and `b < c`, but `a < c` does not hold.
+    NotTransitive,
+    /// `a` is equivalent to `b` and `b` to `c`, but `a` and `c` are not equivalent.
+    /// This is what comparing floats containing NaN with `<` usually looks like.
+    IncomparabilityNotTransitive,
+    /// After merging two sorted runs, a later element compares less than an earlier one.
+    NotSorted,
+}
+
+/// A violation of the strict weak ordering `is_less` must implement, found while sorting.
+///
+/// `indices` are positions in the slice at the time the violation was found, which may differ
+/// from the positions in the input as the slice is partially sorted by then. `values` are copies
+/// of the elements at those positions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComparatorViolation<T> {
+    pub kind: ViolationKind,
+    pub indices: (usize, usize),
+    pub values: (T, T),
+}
+
+impl<T: fmt::Debug> fmt::Display for ComparatorViolation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "comparator violation {:?} between v[{}] = {:?} and v[{}] = {:?}",
+            self.kind, self.indices.0, self.values.0, self.indices.1, self.values.1
+        )
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for ComparatorViolation<T> {}
+
+fn violation<T: Clone>(kind: ViolationKind, v: &[T], i: usize, j: usize) -> ComparatorViolation<T> {
+    ComparatorViolation {
+        kind,
+        indices: (i, j),
+        values: (v[i].clone(), v[j].clone()),
+    }
+}
+
+/// Checks that `v[start..end]` is sorted and that the comparisons between its neighbouring
+/// elements are consistent with a strict weak ordering.
+///
+/// Besides every adjacent pair, each element is compared with the first element of the block of
+/// equivalent elements it follows, which catches non-transitive equivalence (e.g. NaN) that
+/// adjacent comparisons alone can't see.
+fn check_sorted<T, F>(v: &[T], start: usize, end: usize, is_less: &mut F) -> Result<(), ComparatorViolation<T>>
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> bool,
+{
+    // First element of the current block of equivalent elements.
+    let mut block = start;
+
+    for k in start..end.saturating_sub(1) {
+        let less = is_less(&v[k], &v[k + 1]);
+        let greater = is_less(&v[k + 1], &v[k]);
+
+        if less && greater {
+            return Err(violation(ViolationKind::NotAsymmetric, v, k, k + 1));
+        }
+        if greater {
+            return Err(violation(ViolationKind::NotSorted, v, k, k + 1));
+        }
+
+        if less {
+            // Everything equivalent to `v[k]` must be less than `v[k + 1]`.
+            if block != k && !is_less(&v[block], &v[k + 1]) {
+                return Err(violation(ViolationKind::NotTransitive, v, block, k + 1));
+            }
+            block = k + 1;
+        } else if block != k && (is_less(&v[block], &v[k + 1]) || is_less(&v[k + 1], &v[block])) {
+            return Err(violation(ViolationKind::IncomparabilityNotTransitive, v, block, k + 1));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every run found and the result of every merge of a [power_sort_hooked] call.
+struct Checks;
+
+impl<T: Clone> SortHooks<T> for Checks {
+    type Stop = ComparatorViolation<T>;
+
+    fn run_found<F>(
+        &mut self,
+        v: &[T],
+        start: usize,
+        end: usize,
+        _pending: &Pending,
+        is_less: &mut F,
+    ) -> Result<(), ComparatorViolation<T>>
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        check_sorted(v, start, end, is_less)
+    }
+
+    fn merged<F>(
+        &mut self,
+        v: &[T],
+        start: usize,
+        end: usize,
+        _pending: &Pending,
+        is_less: &mut F,
+    ) -> Result<(), ComparatorViolation<T>>
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        check_sorted(v, start, end, is_less)
+    }
+}
+
+/// Like [power_sort](crate::powersort_final::power_sort), but checks that `is_less` behaves like
+/// a strict weak ordering while sorting, for debugging comparison functions.
+///
+/// Before sorting, every element is checked for irreflexivity. Every run found, and the result of
+/// every merge, is checked with an extra pass over it. The first inconsistency found is returned
+/// as a [ComparatorViolation], in which case `v` is left as some permutation of its input.
+///
+/// The checks cost *O*(*n* \* log(*n*)) additional comparisons and are not exhaustive, a
+/// comparator that passes them is not guaranteed to be correct.
+pub fn power_sort_checked<T, F>(v: &mut [T], mut is_less: F) -> Result<(), ComparatorViolation<T>>
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> bool,
+{
+    if size_of::<T>() == 0 || v.is_empty() {
+        return Ok(());
+    }
+
+    for i in 0..v.len() {
+        if is_less(&v[i], &v[i]) {
+            return Err(violation(ViolationKind::NotIrreflexive, v, i, i));
+        }
+    }
+
+    let n = v.len();
+
+    // Buffer for merging runs
+    let mut buf = Vec::with_capacity(n / 2);
+
+    unsafe { power_sort_hooked(v, &mut is_less, buf.as_mut_ptr(), n / 2, &mut Checks) }
+}
+
+#[cfg(test)]
+mod power_sort_checked_tests {
+    use super::{power_sort_checked, ViolationKind};
+    use crate::sequences::generate_m_runs;
+
+    #[test]
+    // A consistent comparator sorts like power_sort.
+    fn power_sort_checked_test_1() {
+        let mut v = generate_m_runs(500, 50);
+        let mut sorted = v.clone();
+        sorted.sort();
+
+        assert_eq!(power_sort_checked(&mut v, |a, b| a < b), Ok(()));
+        assert_eq!(v, sorted);
+    }
+
+    #[test]
+    fn power_sort_checked_test_2() {
+        let mut v = vec![3, 1, 2];
+        let err = power_sort_checked(&mut v, |a, b| a <= b).unwrap_err();
+        assert_eq!(err.kind, ViolationKind::NotIrreflexive);
+        assert_eq!(err.indices.0, err.indices.1);
+    }
+
+    #[test]
+    // NaN is equivalent to every number under `<`, which isn't transitive.
+    fn power_sort_checked_test_3() {
+        let mut v = vec![3.0, f64::NAN, 1.0];
+        let err = power_sort_checked(&mut v, |a, b| a < b).unwrap_err();
+        assert_eq!(err.kind, ViolationKind::IncomparabilityNotTransitive);
+        assert_eq!(err.values.0, 3.0);
+        assert_eq!(err.values.1, 1.0);
+    }
+
+    #[test]
+    // Violations are reported on long inputs, and the slice remains a permutation.
+    fn power_sort_checked_test_4() {
+        let mut v: Vec<f64> = generate_m_runs(500, 50).into_iter().map(|x| x as f64).collect();
+        for i in (0..v.len()).step_by(37) {
+            v[i] = f64::NAN;
+        }
+        let mut before: Vec<u64> = v.iter().map(|x| x.to_bits()).collect();
+        before.sort();
+
+        assert!(power_sort_checked(&mut v, |a, b| a < b).is_err());
+
+        let mut after: Vec<u64> = v.iter().map(|x| x.to_bits()).collect();
+        after.sort();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    // A comparator claiming everything is less than everything else.
+    fn power_sort_checked_test_5() {
+        let mut v = vec![1, 2];
+        let mut calls = 0;
+        let err = power_sort_checked(&mut v, |a, b| {
+            calls += 1;
+            // Pass the irreflexivity checks, then claim everything is less.
+            calls > 2 || a < b
+        })
+        .unwrap_err();
+        assert_eq!(err.kind, ViolationKind::NotAsymmetric);
+    }
+}
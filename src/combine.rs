@@ -0,0 +1,194 @@
+//! Sort-based aggregation: sorting records while folding together those with equal keys.
+
+use std::iter::Peekable;
+use std::mem;
+use std::vec::IntoIter;
+
+use crate::powersort_final::{RunStack, MIN_RUN_LENGTH};
+
+/// Takes the run at the front of `input`, combining elements with equal keys as they are found,
+/// and makes it strictly increasing by key. Short runs are extended to the minimum run length by
+/// binary insertion. Returns the run and the number of input elements it was built from.
+fn next_run<T, E, C>(
+    input: &mut Peekable<IntoIter<T>>,
+    key_eq: &mut E,
+    combine: &mut C,
+) -> (Vec<T>, usize)
+where
+    T: Ord,
+    E: FnMut(&T, &T) -> bool,
+    C: FnMut(&mut T, T),
+{
+    let mut run = Vec::new();
+    run.extend(input.next());
+    let mut length = run.len();
+
+    // Whether the run is decreasing, unknown until two different keys are found.
+    let mut is_decreasing = None;
+
+    while let Some(next) = input.peek() {
+        let last = run.last().unwrap();
+        if key_eq(last, next) {
+            // Equal keys are combined whatever the direction of the run.
+            combine(run.last_mut().unwrap(), input.next().unwrap());
+        } else if is_decreasing.is_none() || is_decreasing == Some(next < last) {
+            is_decreasing = Some(next < last);
+            run.push(input.next().unwrap());
+        } else {
+            break;
+        }
+        length += 1;
+    }
+
+    if is_decreasing == Some(true) {
+        run.reverse();
+    }
+
+    while length < MIN_RUN_LENGTH {
+        let Some(next) = input.next() else { break };
+        // Keys are contiguous in the order, so an element with the same key is next to where
+        // `next` would go.
+        let position = run.partition_point(|x| *x < next);
+        if position < run.len() && key_eq(&run[position], &next) {
+            combine(&mut run[position], next);
+        } else if position > 0 && key_eq(&run[position - 1], &next) {
+            combine(&mut run[position - 1], next);
+        } else {
+            run.insert(position, next);
+        }
+        length += 1;
+    }
+
+    (run, length)
+}
+
+/// Merges the runs `left` and `right`, strictly increasing by key, combining pairs of elements
+/// with equal keys.
+fn merge_combine<T, E, C>(left: Vec<T>, right: Vec<T>, key_eq: &mut E, combine: &mut C) -> Vec<T>
+where
+    T: Ord,
+    E: FnMut(&T, &T) -> bool,
+    C: FnMut(&mut T, T),
+{
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut right = right.into_iter().peekable();
+
+    for mut l in left {
+        while let Some(r) = right.peek() {
+            if key_eq(&l, r) {
+                // Elements of the left run came first, so they absorb the equal elements of the
+                // right one.
+                combine(&mut l, right.next().unwrap());
+            } else if *r < l {
+                merged.push(right.next().unwrap());
+            } else {
+                break;
+            }
+        }
+        merged.push(l);
+    }
+
+    merged.extend(right);
+    merged
+}
+
+/// Sorts `v` with PowerSort while combining elements with equal keys, and returns one element per
+/// distinct key, in order.
+///
+/// Elements are sorted by their `Ord` order, `key_eq` tells whether two of them have the same key,
+/// and then `combine(a, b)` folds `b` into `a`. The elements with a given key must be next to each
+/// other in the order, as they are for `(key, value)` pairs with `key_eq` comparing the keys, and
+/// combining must not change the key. Elements are combined as soon as they meet: next to each
+/// other in a run, and inside every merge. The data shrinks early when there are many duplicates,
+/// which makes later merges cheaper, like the sort-based aggregation of a database's group by.
+///
+/// Elements with equal keys are combined in their original order, so `combine` doesn't need to be
+/// commutative, only associative. Runs are merged in
+/// [node_power](crate::powersort_final::node_power) order, computed on their positions in the
+/// input.
+pub fn power_sort_combine<T, E, C>(v: Vec<T>, mut key_eq: E, mut combine: C) -> Vec<T>
+where
+    T: Ord,
+    E: FnMut(&T, &T) -> bool,
+    C: FnMut(&mut T, T),
+{
+    let n = v.len();
+    let mut input = v.into_iter().peekable();
+
+    // Stack for storing runs, their lengths counting the input elements they were built from.
+    let mut runs: RunStack<Vec<T>> = RunStack::new(n);
+
+    // Look for runs and merge if possible.
+    while input.peek().is_some() {
+        let (run, length) = next_run(&mut input, &mut key_eq, &mut combine);
+        runs.push(run, length, |left, right| {
+            *left = merge_combine(mem::take(left), mem::take(right), &mut key_eq, &mut combine);
+        });
+    }
+
+    // Merge remaining runs.
+    let merged = runs.finish(|left, right| {
+        *left = merge_combine(mem::take(left), mem::take(right), &mut key_eq, &mut combine);
+    });
+    merged.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod power_sort_combine_tests {
+    use std::collections::BTreeMap;
+
+    use super::power_sort_combine;
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    // Summing values by key gives the same result as a map.
+    fn power_sort_combine_test_1() {
+        for keys in [generate_random_sequence_in_range(5000, 0, 100), generate_m_runs(3000, 40)] {
+            let v: Vec<(i32, u64)> = keys.iter().map(|&k| (k, (k as u64 % 7) + 1)).collect();
+            let mut expected: BTreeMap<i32, u64> = BTreeMap::new();
+            for &(k, x) in &v {
+                *expected.entry(k).or_default() += x;
+            }
+
+            let reduced = power_sort_combine(v, |a, b| a.0 == b.0, |a, b| a.1 += b.1);
+            assert!(reduced.into_iter().eq(expected.into_iter()));
+        }
+    }
+
+    #[test]
+    // Equal keys are combined in their original order.
+    fn power_sort_combine_test_2() {
+        let keys = generate_random_sequence_in_range(2000, 0, 30);
+        let v: Vec<(i32, Vec<usize>)> = keys.iter().zip(0..).map(|(&k, i)| (k, vec![i])).collect();
+
+        let reduced = power_sort_combine(v, |a, b| a.0 == b.0, |a, b| a.1.extend(b.1));
+        assert!(reduced.windows(2).all(|w| w[0].0 < w[1].0));
+        for (k, ids) in reduced {
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            assert!(ids.iter().all(|&i| keys[i] == k));
+        }
+    }
+
+    #[test]
+    fn power_sort_combine_test_3() {
+        let v = vec![3, 3, 2, 2, 2, 1, 5, 5];
+        assert_eq!(power_sort_combine(v, |a, b| a == b, |_, _| {}), vec![1, 2, 3, 5]);
+        assert!(power_sort_combine(Vec::<i32>::new(), |a, b| a == b, |_, _| {}).is_empty());
+    }
+
+    #[test]
+    // Later elements may come first in the order within a key, and are still combined into the
+    // earlier ones.
+    fn power_sort_combine_test_4() {
+        let keys = generate_random_sequence_in_range(2000, 0, 30);
+        let v: Vec<(i32, Vec<usize>)> =
+            keys.iter().zip(0..).map(|(&k, i)| (k, vec![2000 - i])).collect();
+
+        let reduced = power_sort_combine(v, |a, b| a.0 == b.0, |a, b| a.1.extend(b.1));
+        assert!(reduced.windows(2).all(|w| w[0].0 < w[1].0));
+        for (k, ids) in reduced {
+            assert!(ids.windows(2).all(|w| w[0] > w[1]));
+            assert!(ids.iter().all(|&i| keys[2000 - i] == k));
+        }
+    }
+}
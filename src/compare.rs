@@ -0,0 +1,270 @@
+//! Building comparators out of smaller pieces, instead of writing multi-key `is_less` closures by
+//! hand.
+//!
+//! A comparator returns the `Ordering` of two values. They are combined with
+//! [then](Comparator::then) and [reverse](Comparator::reverse), and turned into an `is_less` for
+//! [power_sort](crate::powersort_final::power_sort) with [into_is_less](Comparator::into_is_less):
+//!
+//! ```
+//! use powersort::compare::{by_key, by_ref_key, case_insensitive, on, Comparator};
+//! use powersort::powersort_final::power_sort;
+//!
+//! struct Record { date: u32, name: String, id: usize }
+//!
+//! let mut v = vec![
+//!     Record { date: 1, name: "b".to_string(), id: 0 },
+//!     Record { date: 2, name: "a".to_string(), id: 1 },
+//!     Record { date: 1, name: "A".to_string(), id: 2 },
+//! ];
+//! let order = by_key(|r: &Record| r.date)
+//!     .reverse()
+//!     .then(on(|r: &Record| r.name.as_str(), case_insensitive()))
+//!     .then(by_ref_key(|r: &Record| &r.id));
+//! power_sort(&mut v, order.into_is_less());
+//! assert_eq!(v.iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 2, 0]);
+//! ```
+//!
+//! The [sort_by_fields](crate::sort_by_fields) macro covers the common case of sorting by fields
+//! in ascending or descending order.
+
+use std::cmp::Ordering;
+
+/// Compares two values.
+///
+/// Implemented by every `Fn(&T, &T) -> Ordering`, and by the comparators built by this module.
+pub trait Comparator<T: ?Sized> {
+    /// Returns the ordering of `a` and `b`.
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+
+    /// Compares with `self`, then with `next` if the values are equal.
+    fn then<C: Comparator<T>>(self, next: C) -> impl Comparator<T>
+    where
+        Self: Sized,
+    {
+        move |a: &T, b: &T| self.compare(a, b).then_with(|| next.compare(a, b))
+    }
+
+    /// Reverses the order given by `self`.
+    fn reverse(self) -> impl Comparator<T>
+    where
+        Self: Sized,
+    {
+        move |a: &T, b: &T| self.compare(b, a)
+    }
+
+    /// Returns an `is_less` function for the order given by `self`, to be passed to the sorts.
+    fn into_is_less(self) -> impl Fn(&T, &T) -> bool
+    where
+        Self: Sized,
+    {
+        move |a: &T, b: &T| self.compare(a, b) == Ordering::Less
+    }
+}
+
+impl<T: ?Sized, F> Comparator<T> for F
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// Compares values by their `Ord` implementation.
+pub fn natural<T: Ord + ?Sized>() -> impl Comparator<T> {
+    |a: &T, b: &T| a.cmp(b)
+}
+
+/// Compares values by the key returned by `f`.
+pub fn by_key<T, K, F>(f: F) -> impl Comparator<T>
+where
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    move |a: &T, b: &T| f(a).cmp(&f(b))
+}
+
+/// Compares values by a key borrowed from them, such as a `&str` to one of their fields.
+pub fn by_ref_key<T, K, F>(f: F) -> impl Comparator<T>
+where
+    K: Ord + ?Sized,
+    F: Fn(&T) -> &K,
+{
+    move |a: &T, b: &T| f(a).cmp(f(b))
+}
+
+/// Compares values by a key borrowed from them, using `comparator` to compare the keys.
+pub fn on<T, K, F, C>(f: F, comparator: C) -> impl Comparator<T>
+where
+    K: ?Sized,
+    F: Fn(&T) -> &K,
+    C: Comparator<K>,
+{
+    move |a: &T, b: &T| comparator.compare(f(a), f(b))
+}
+
+/// Compares options, with `None` before every `Some`. Values in `Some` are compared with
+/// `comparator`.
+pub fn nulls_first<T, C: Comparator<T>>(comparator: C) -> impl Comparator<Option<T>> {
+    move |a: &Option<T>, b: &Option<T>| match (a, b) {
+        (Some(a), Some(b)) => comparator.compare(a, b),
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+/// Compares options, with `None` after every `Some`. Values in `Some` are compared with
+/// `comparator`.
+pub fn nulls_last<T, C: Comparator<T>>(comparator: C) -> impl Comparator<Option<T>> {
+    move |a: &Option<T>, b: &Option<T>| match (a, b) {
+        (Some(a), Some(b)) => comparator.compare(a, b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    }
+}
+
+/// Compares strings ignoring case, by comparing their lowercase characters.
+pub fn case_insensitive<S: AsRef<str> + ?Sized>() -> impl Comparator<S> {
+    fn lower(s: &str) -> impl Iterator<Item = char> + '_ {
+        s.chars().flat_map(char::to_lowercase)
+    }
+
+    |a: &S, b: &S| lower(a.as_ref()).cmp(lower(b.as_ref()))
+}
+
+/// Returns an `is_less` function comparing values by a list of fields, each in ascending order or
+/// in descending order if followed by `desc`. Fields are compared with their `Ord` implementation.
+///
+/// The function must be passed directly to a sort so the type of the values can be inferred.
+///
+/// ```
+/// use powersort::fields_is_less;
+/// use powersort::powersort_final::power_sort;
+///
+/// let mut v = vec![(1, "b"), (2, "a"), (1, "a")];
+/// power_sort(&mut v, fields_is_less!(.0 desc, .1));
+/// assert_eq!(v, vec![(2, "a"), (1, "a"), (1, "b")]);
+/// ```
+#[macro_export]
+macro_rules! fields_is_less {
+    (@cmp $a:expr, $b:expr) => {
+        ::std::cmp::Ord::cmp(&$a, &$b)
+    };
+    (@cmp asc $a:expr, $b:expr) => {
+        ::std::cmp::Ord::cmp(&$a, &$b)
+    };
+    (@cmp desc $a:expr, $b:expr) => {
+        ::std::cmp::Ord::cmp(&$b, &$a)
+    };
+    ($(. $field:tt $($direction:ident)?),+ $(,)?) => {
+        |a, b| {
+            let ordering = ::std::cmp::Ordering::Equal;
+            $(
+                let ordering = ordering.then_with(|| $crate::fields_is_less!(@cmp $($direction)? a.$field, b.$field));
+            )+
+            ordering == ::std::cmp::Ordering::Less
+        }
+    };
+}
+
+/// Sorts a slice with [power_sort](crate::powersort_final::power_sort) by a list of fields, see
+/// [fields_is_less](crate::fields_is_less).
+///
+/// ```
+/// use powersort::sort_by_fields;
+///
+/// struct Record { date: u32, name: &'static str, id: usize }
+///
+/// let mut v = vec![
+///     Record { date: 1, name: "b", id: 0 },
+///     Record { date: 2, name: "a", id: 1 },
+///     Record { date: 1, name: "a", id: 2 },
+/// ];
+/// sort_by_fields!(v, .date desc, .name, .id);
+/// assert_eq!(v.iter().map(|r| r.id).collect::<Vec<_>>(), vec![1, 2, 0]);
+/// ```
+#[macro_export]
+macro_rules! sort_by_fields {
+    ($v:expr, $($fields:tt)+) => {
+        $crate::powersort_final::power_sort(&mut $v, $crate::fields_is_less!($($fields)+))
+    };
+}
+
+#[cfg(test)]
+mod compare_tests {
+    use std::cmp::Ordering;
+
+    use super::{by_key, by_ref_key, case_insensitive, natural, nulls_first, nulls_last, on, Comparator};
+    use crate::powersort_final::power_sort;
+    use crate::sequences::generate_random_sequence_in_range;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Record {
+        date: Option<u32>,
+        name: String,
+        id: usize,
+    }
+
+    fn records() -> Vec<Record> {
+        let keys = generate_random_sequence_in_range(500, 0, 10);
+        keys.iter()
+            .zip(0..)
+            .map(|(&k, id)| Record {
+                date: if k == 0 { None } else { Some(k as u32 % 4) },
+                name: if k % 2 == 0 { format!("N{}", k) } else { format!("n{}", k) },
+                id,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compare_test_1() {
+        assert_eq!(natural::<str>().compare("a", "b"), Ordering::Less);
+        assert_eq!(case_insensitive::<str>().compare("Straße", "STRASSE"), Ordering::Greater);
+        assert_eq!(case_insensitive::<String>().compare(&"ABC".to_string(), &"abc".to_string()), Ordering::Equal);
+        assert_eq!(nulls_first(natural()).compare(&None, &Some(1)), Ordering::Less);
+        assert_eq!(nulls_last(natural()).compare(&None, &Some(1)), Ordering::Greater);
+        assert_eq!(nulls_last(natural::<i32>().reverse()).compare(&Some(1), &Some(2)), Ordering::Greater);
+    }
+
+    #[test]
+    // Combinators give the same order as an equivalent hand-written comparison.
+    fn compare_test_2() {
+        let mut v = records();
+        let mut expected = v.clone();
+        expected.sort_by(|a, b| {
+            (a.date.is_none(), b.date)
+                .cmp(&(b.date.is_none(), a.date))
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+
+        let order = on(|r: &Record| &r.date, nulls_last(natural::<u32>().reverse()))
+            .then(on(|r: &Record| r.name.as_str(), case_insensitive()));
+        power_sort(&mut v, order.into_is_less());
+        assert_eq!(v, expected);
+    }
+
+    #[test]
+    // Reversing a combined comparator reverses every key.
+    fn compare_test_3() {
+        let mut v = records();
+        let mut expected = v.clone();
+        expected.sort_by_key(|r| std::cmp::Reverse((r.date.is_some(), r.id)));
+
+        let order = by_key(|r: &Record| r.date.is_some()).then(by_ref_key(|r: &Record| &r.id));
+        power_sort(&mut v, order.reverse().into_is_less());
+        assert_eq!(v, expected);
+    }
+
+    #[test]
+    fn sort_by_fields_test_1() {
+        let mut v = records();
+        let mut expected = v.clone();
+        expected.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.name.cmp(&b.name)).then_with(|| a.id.cmp(&b.id)));
+
+        sort_by_fields!(v, .date desc, .name, .id);
+        assert_eq!(v, expected);
+
+        let mut pairs = vec![(2, 'b'), (1, 'a'), (2, 'a')];
+        sort_by_fields!(pairs, .0 asc, .1 desc);
+        assert_eq!(pairs, vec![(1, 'a'), (2, 'b'), (2, 'a')]);
+    }
+}
@@ -0,0 +1,205 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::SortError;
+use crate::powersort_final::{insertion_sort, power_sort_hooked, Pending, SortHooks, MAX_INSERTION};
+
+/// Observes and steers a [power_sort_with_control] call.
+///
+/// The progress callback is called with an estimate of the fraction of the work done so far, where
+/// the total work is `n` for finding the runs plus the merge cost, the sum of the lengths of all
+/// merges. The cancellation flag may be set from another thread, and is checked after every run
+/// found and every merge.
+#[derive(Default)]
+pub struct Control<'a> {
+    cancel: Option<&'a AtomicBool>,
+    progress: Option<Box<dyn FnMut(f64) + 'a>>,
+}
+
+impl<'a> Control<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the sort as soon as `flag` is set.
+    pub fn with_cancel_flag(mut self, flag: &'a AtomicBool) -> Self {
+        self.cancel = Some(flag);
+        self
+    }
+
+    /// Calls `progress` with a fraction in `[0, 1]` after every run found and every merge.
+    pub fn with_progress<P>(mut self, progress: P) -> Self
+    where
+        P: FnMut(f64) + 'a,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    fn report(&mut self, done: usize, total: usize) {
+        if let Some(progress) = self.progress.as_mut() {
+            progress(done as f64 / total as f64);
+        }
+    }
+}
+
+/// Reports the progress of a [power_sort_hooked] call to a [Control], and stops it once cancelled.
+struct ControlHooks<'c, 'a> {
+    control: &'c mut Control<'a>,
+    done: usize,
+}
+
+impl ControlHooks<'_, '_> {
+    fn advance(&mut self, work: usize, pending: &Pending) -> Result<(), SortError> {
+        self.done += work;
+        self.control.report(self.done, self.done + pending.remaining_work());
+
+        if self.control.is_cancelled() {
+            return Err(SortError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+impl<T> SortHooks<T> for ControlHooks<'_, '_> {
+    type Stop = SortError;
+
+    fn run_found<F>(
+        &mut self,
+        _v: &[T],
+        start: usize,
+        end: usize,
+        pending: &Pending,
+        _is_less: &mut F,
+    ) -> Result<(), SortError>
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        self.advance(end - start, pending)
+    }
+
+    fn merged<F>(
+        &mut self,
+        _v: &[T],
+        start: usize,
+        end: usize,
+        pending: &Pending,
+        _is_less: &mut F,
+    ) -> Result<(), SortError>
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        self.advance(end - start, pending)
+    }
+}
+
+/// Like [power_sort](crate::powersort_final::power_sort), but reports progress to `control` and
+/// stops early if it is cancelled.
+///
+/// The total work isn't known until every run is found, so each report divides the work done by
+/// the work done plus an upper bound on the work left, which the sort tightens as it goes. The
+/// reported fraction never decreases and reaches 1 once the slice is sorted, with the last merge or
+/// with the only run found. A cancelled sort returns [SortError::Cancelled] and leaves `v` as a
+/// permutation of its input, with the merges done so far applied.
+pub fn power_sort_with_control<T, F>(
+    v: &mut [T],
+    mut is_less: F,
+    control: &mut Control,
+) -> Result<(), SortError>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    if control.is_cancelled() {
+        return Err(SortError::Cancelled);
+    }
+
+    let n = v.len();
+
+    if size_of::<T>() == 0 {
+        return Ok(());
+    }
+
+    if n < MAX_INSERTION {
+        insertion_sort(v, &mut is_less);
+        control.report(1, 1);
+        return Ok(());
+    }
+
+    // Buffer for merging runs
+    let mut buf = Vec::with_capacity(n / 2);
+
+    let mut hooks = ControlHooks { control, done: 0 };
+    unsafe { power_sort_hooked(v, &mut is_less, buf.as_mut_ptr(), n / 2, &mut hooks) }
+}
+
+#[cfg(test)]
+mod power_sort_with_control_tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::{power_sort_with_control, Control};
+    use crate::error::SortError;
+    use crate::sequences::generate_m_runs;
+
+    #[test]
+    // Progress grows monotonically and ends at exactly 1.
+    fn power_sort_with_control_test_1() {
+        let mut v = generate_m_runs(1000, 40);
+        let mut sorted = v.clone();
+        sorted.sort();
+
+        let mut reports = vec![];
+        let mut control = Control::new().with_progress(|p| reports.push(p));
+        assert_eq!(power_sort_with_control(&mut v, |a, b| a < b, &mut control), Ok(()));
+        drop(control);
+
+        assert_eq!(v, sorted);
+        assert!(reports.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(*reports.last().unwrap(), 1.0);
+    }
+
+    #[test]
+    // Cancelling halfway leaves a permutation of the input.
+    fn power_sort_with_control_test_2() {
+        let mut v = generate_m_runs(1000, 40);
+        let mut before = v.clone();
+        before.sort();
+
+        let cancel = AtomicBool::new(false);
+        let mut control = Control::new()
+            .with_cancel_flag(&cancel)
+            .with_progress(|p| if p > 0.5 { cancel.store(true, Ordering::Relaxed) });
+
+        assert_eq!(power_sort_with_control(&mut v, |a, b| a < b, &mut control), Err(SortError::Cancelled));
+
+        v.sort();
+        assert_eq!(v, before);
+    }
+
+    #[test]
+    fn power_sort_with_control_test_3() {
+        let mut v = vec![3, 2, 1];
+        let cancel = AtomicBool::new(true);
+        let mut control = Control::new().with_cancel_flag(&cancel);
+
+        assert_eq!(power_sort_with_control(&mut v, |a, b| a < b, &mut control), Err(SortError::Cancelled));
+        assert_eq!(v, vec![3, 2, 1]);
+    }
+
+    #[test]
+    // A single run needs no merges, and progress still ends at 1.
+    fn power_sort_with_control_test_4() {
+        let mut v: Vec<i32> = (0..100).collect();
+
+        let mut reports = vec![];
+        let mut control = Control::new().with_progress(|p| reports.push(p));
+        assert_eq!(power_sort_with_control(&mut v, |a, b| a < b, &mut control), Ok(()));
+        drop(control);
+
+        assert!(v.iter().copied().eq(0..100));
+        assert_eq!(reports, vec![1.0]);
+    }
+}
@@ -0,0 +1,21 @@
+use std::fmt;
+
+/// Errors reported by the fallible sorting functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortError {
+    /// The scratch buffer used for merging runs could not be allocated.
+    AllocFailed,
+    /// The sort was cancelled before it finished.
+    Cancelled,
+}
+
+impl fmt::Display for SortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortError::AllocFailed => write!(f, "failed to allocate the scratch buffer for merging"),
+            SortError::Cancelled => write!(f, "the sort was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for SortError {}
@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+
+use crate::powersort_final::power_sort;
+
+/// Where NaNs end up when sorting floats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NanOrder {
+    First,
+    Last,
+}
+
+/// Floating point types that can be sorted with [power_sort_floats].
+pub trait Float: Copy + private::Sealed {
+    fn is_nan(self) -> bool;
+    fn total_cmp(&self, other: &Self) -> Ordering;
+}
+
+impl Float for f32 {
+    fn is_nan(self) -> bool {
+        f32::is_nan(self)
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+}
+
+impl Float for f64 {
+    fn is_nan(self) -> bool {
+        f64::is_nan(self)
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f64::total_cmp(self, other)
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for f32 {}
+    impl Sealed for f64 {}
+}
+
+/// Returns true if `a` goes before `b`.
+///
+/// Numbers follow the IEEE 754 total order, which also puts -0.0 before +0.0. All NaNs, whatever
+/// their sign or payload, are equivalent to each other and go before or after every number.
+fn float_is_less<F: Float>(a: F, b: F, nans: NanOrder) -> bool {
+    match (a.is_nan(), b.is_nan()) {
+        (false, false) => a.total_cmp(&b) == Ordering::Less,
+        (true, true) => false,
+        (true, false) => nans == NanOrder::First,
+        (false, true) => nans == NanOrder::Last,
+    }
+}
+
+/// Sorts a slice of floats with PowerSort.
+///
+/// Unlike comparing with `<`, this is a valid total order even if `v` contains NaNs: they are all
+/// placed at the start or end of `v` as given by `nans`, in their original order. Zeros are sorted
+/// by sign, -0.0 before +0.0.
+pub fn power_sort_floats<F: Float>(v: &mut [F], nans: NanOrder) {
+    power_sort(v, |&a, &b| float_is_less(a, b, nans));
+}
+
+/// Sorts `v` by a float key with PowerSort. See [power_sort_floats] for how the keys are ordered.
+///
+/// The sort is stable, and `key` is called twice per comparison.
+pub fn power_sort_by_float_key<T, F, K>(v: &mut [T], mut key: K, nans: NanOrder)
+where
+    F: Float,
+    K: FnMut(&T) -> F,
+{
+    power_sort(v, |a, b| float_is_less(key(a), key(b), nans));
+}
+
+#[cfg(test)]
+mod power_sort_floats_tests {
+    use super::{power_sort_by_float_key, power_sort_floats, NanOrder};
+    use crate::sequences::generate_random_sequence;
+
+    // Random floats with every third element replaced by a NaN of either sign.
+    fn nan_heavy(length: usize) -> Vec<f64> {
+        generate_random_sequence(length)
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| match i % 6 {
+                0 => f64::NAN,
+                3 => -f64::NAN,
+                _ => x as f64 - length as f64 / 2.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn power_sort_floats_test_1() {
+        for nans in [NanOrder::First, NanOrder::Last] {
+            let mut v = nan_heavy(1000);
+            let nan_count = v.iter().filter(|x| x.is_nan()).count();
+            let mut numbers: Vec<f64> = v.iter().copied().filter(|x| !x.is_nan()).collect();
+            numbers.sort_by(|a, b| a.total_cmp(b));
+
+            power_sort_floats(&mut v, nans);
+
+            let (nan_part, number_part) = match nans {
+                NanOrder::First => v.split_at(nan_count),
+                NanOrder::Last => {
+                    let (a, b) = v.split_at(v.len() - nan_count);
+                    (b, a)
+                }
+            };
+            assert!(nan_part.iter().all(|x| x.is_nan()));
+            assert_eq!(number_part, &numbers[..]);
+        }
+    }
+
+    #[test]
+    fn power_sort_floats_test_2() {
+        let mut v = vec![0.0f32, f32::NAN, -0.0, 1.0, f32::NEG_INFINITY, -0.0, f32::INFINITY];
+        power_sort_floats(&mut v, NanOrder::Last);
+
+        let bits: Vec<u32> = v.iter().map(|x| x.to_bits()).collect();
+        let expected = [f32::NEG_INFINITY, -0.0, -0.0, 0.0, 1.0, f32::INFINITY, f32::NAN];
+        assert_eq!(bits, expected.iter().map(|x| x.to_bits()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    // NaN keys are all equivalent, so records keep their relative order.
+    fn power_sort_by_float_key_test_1() {
+        let keys = nan_heavy(600);
+        let mut v: Vec<(f64, usize)> = keys.into_iter().zip(0..).collect();
+        power_sort_by_float_key(&mut v, |x| x.0, NanOrder::First);
+
+        let nan_ids: Vec<usize> = v.iter().take_while(|x| x.0.is_nan()).map(|x| x.1).collect();
+        assert_eq!(nan_ids.len(), 200);
+        assert!(nan_ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(v[200..].windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+}
@@ -0,0 +1,346 @@
+use std::cell::Cell;
+use std::mem::size_of;
+
+use crate::powersort_final::{capacity, insert_sort, merge, merge_cuts, node_power, MIN_RUN_LENGTH};
+
+/// The state of an [IncrementalPowerSort] after a call to [IncrementalPowerSort::step].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// There is more work to do.
+    Pending,
+    /// The slice is sorted.
+    Done,
+}
+
+/// The run `v[end + 1 - length..=end]`. On the stack, `power` is the power of the boundary on its
+/// left, and for the run just found in [State::Collapse], of the boundary on its right.
+///
+/// The merges of an [IncrementalPowerSort] are spread over many calls, so it keeps its own stack
+/// rather than a [RunStack](crate::powersort_final::RunStack), which merges as runs are pushed.
+#[derive(Clone, Copy, Debug)]
+struct Run {
+    end: usize,
+    length: usize,
+    power: usize,
+}
+
+/// A piece of the merge in progress.
+#[derive(Clone, Copy, Debug)]
+enum Work {
+    /// Merge `v[start..mid]` and `v[mid..end]`.
+    Merge(usize, usize, usize),
+    /// Rotate `v[start..end]` to the left by `mid - start`, one block swap at a time.
+    Rotate(usize, usize, usize),
+    /// Swap the `len` elements at `a` with the `len` elements at `b`.
+    Swap(usize, usize, usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    /// Looking for the run that ends at `end`. `v[start..=end]` is known to be part of it, and
+    /// `increasing` is its direction once two elements have been compared.
+    Scan { end: usize, start: usize, increasing: Option<bool> },
+    /// A run was found, merge the current run with the stack while the power rule says so.
+    Collapse { next: Run },
+    /// All runs were found, merge whatever remains on the stack.
+    Finish,
+    Done,
+}
+
+/// PowerSort spread over many calls, each doing a bounded number of comparisons.
+///
+/// Runs are found and merged in the same order as [power_sort](crate::powersort_final::power_sort),
+/// so the final result is the same. Between calls to [step](Self::step) the slice is always a
+/// permutation of its input: merges that don't fit in the remaining budget are split into smaller
+/// independent merges by rotating the runs, and are picked up again by later calls.
+///
+/// The budget counts comparisons and the elements moved by rotations. Rotations are done with
+/// block swaps, which can stop after any swap, so a rotation of a long run is spread over as many
+/// calls as its cost needs.
+pub struct IncrementalPowerSort<'a, T, F> {
+    v: &'a mut [T],
+    is_less: F,
+    state: State,
+    /// The run currently being merged into the stack, as `(start, length)`.
+    current: (usize, usize),
+    runs: Vec<Run>,
+    /// Pending pieces of the merge in progress, the next one on top.
+    work: Vec<Work>,
+    buf: Vec<T>,
+    comparisons: usize,
+}
+
+impl<'a, T, F> IncrementalPowerSort<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    /// Prepares sorting `v`, no comparisons are done until [step](Self::step) is called.
+    pub fn new(v: &'a mut [T], is_less: F) -> Self {
+        let n = v.len();
+        let state = if n < 2 || size_of::<T>() == 0 {
+            State::Done
+        } else {
+            State::Scan { end: n - 1, start: n - 1, increasing: None }
+        };
+
+        IncrementalPowerSort {
+            v,
+            is_less,
+            state,
+            current: (n, 0),
+            runs: Vec::with_capacity(capacity(n)),
+            work: Vec::new(),
+            buf: Vec::with_capacity(n / 2),
+            comparisons: 0,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// The number of comparisons done so far.
+    pub fn comparisons(&self) -> usize {
+        self.comparisons
+    }
+
+    /// Runs until the slice is sorted.
+    pub fn finish(&mut self) {
+        while self.step(usize::MAX) == Progress::Pending {}
+    }
+
+    /// Does about `budget` comparisons and moves worth of run detection and merging.
+    ///
+    /// A step may go over the budget by a small amount: extending a short run with insertion sort,
+    /// and finding where to split a merge, are never interrupted.
+    pub fn step(&mut self, budget: usize) -> Progress {
+        let n = self.v.len();
+        let used = Cell::new(0usize);
+        let inner = &mut self.is_less;
+        let mut is_less = |a: &T, b: &T| {
+            used.set(used.get() + 1);
+            inner(a, b)
+        };
+
+        // Elements moved by rotations.
+        let mut moved = 0;
+
+        while used.get() + moved < budget && !matches!(self.state, State::Done) {
+            let remaining = budget - used.get() - moved;
+
+            // Finish the merge in progress first.
+            if let Some(work) = self.work.pop() {
+                match work {
+                    Work::Merge(start, mid, end) => {
+                        if mid == start || mid == end {
+                            continue;
+                        }
+
+                        let v = &mut self.v[start..end];
+                        let mid = mid - start;
+                        if v.len() - 1 <= remaining {
+                            unsafe { merge(v, mid, self.buf.as_mut_ptr(), &mut is_less) };
+                        } else if v.len() == 2 {
+                            if is_less(&v[1], &v[0]) {
+                                v.swap(0, 1);
+                            }
+                        } else {
+                            // The two smaller merges wait for the rotation that separates them.
+                            let (left_cut, right_cut) = merge_cuts(v, mid, &mut is_less);
+                            let new_mid = start + left_cut + (right_cut - mid);
+                            let (left_cut, right_cut) = (start + left_cut, start + right_cut);
+                            self.work.push(Work::Merge(new_mid, new_mid + (start + mid - left_cut), end));
+                            self.work.push(Work::Merge(start, left_cut, new_mid));
+                            self.work.push(Work::Rotate(left_cut, start + mid, right_cut));
+                        }
+                    }
+                    Work::Rotate(start, mid, end) => {
+                        if mid == start || mid == end {
+                            continue;
+                        }
+
+                        // Swap the shorter side into place, which leaves a shorter rotation.
+                        let (p, q) = (mid - start, end - mid);
+                        if p <= q {
+                            self.work.push(Work::Rotate(start + p, mid + p, end));
+                            self.work.push(Work::Swap(start, mid, p));
+                        } else {
+                            self.work.push(Work::Rotate(start, mid - q, end - q));
+                            self.work.push(Work::Swap(mid - q, mid, q));
+                        }
+                    }
+                    Work::Swap(a, b, len) => {
+                        // Every swap moves two elements.
+                        let count = len.min(remaining.div_ceil(2));
+                        for i in 0..count {
+                            self.v.swap(a + i, b + i);
+                        }
+                        moved += 2 * count;
+
+                        if count < len {
+                            self.work.push(Work::Swap(a + count, b + count, len - count));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            match self.state {
+                State::Scan { end, mut start, mut increasing } => {
+                    let v = &mut *self.v;
+
+                    // Extend the run to the left as far as the budget allows.
+                    let mut complete = start == 0;
+                    while !complete && used.get() + moved < budget {
+                        let less = is_less(&v[start], &v[start - 1]);
+                        match increasing {
+                            None => increasing = Some(!less),
+                            Some(true) if less => complete = true,
+                            Some(false) if !less => complete = true,
+                            _ => {}
+                        }
+                        if !complete {
+                            start -= 1;
+                            complete = start == 0;
+                        }
+                    }
+
+                    if !complete {
+                        self.state = State::Scan { end, start, increasing };
+                        continue;
+                    }
+
+                    // Reverse the run if it is decreasing so we only have (weakly) increasing runs.
+                    if increasing == Some(false) {
+                        v[start..=end].reverse();
+                    }
+                    while end - start + 1 < MIN_RUN_LENGTH && start > 0 {
+                        start -= 1;
+                        insert_sort(&mut v[start..=end], &mut is_less);
+                    }
+
+                    let length = end - start + 1;
+                    if self.current.0 == n {
+                        // This is the first run, there is nothing to merge it with yet.
+                        self.current = (start, length);
+                        self.state = next_scan(start);
+                    } else {
+                        let (s1, n1) = self.current;
+                        let power = node_power(s1, n1, length, n);
+                        self.state = State::Collapse { next: Run { end, length, power } };
+                    }
+                }
+                State::Collapse { next } => {
+                    let (s1, n1) = self.current;
+                    match self.runs.last() {
+                        // If the top run's power is greater than the power of the new run, merge them.
+                        Some(top) if top.power > next.power => {
+                            let top = self.runs.pop().unwrap();
+                            self.work.push(Work::Merge(s1, s1 + n1, top.end + 1));
+                            self.current = (s1, n1 + top.length);
+                        }
+                        _ => {
+                            self.runs.push(Run { end: s1 + n1 - 1, length: n1, power: next.power });
+                            let start = next.end + 1 - next.length;
+                            self.current = (start, next.length);
+                            self.state = next_scan(start);
+                        }
+                    }
+                }
+                State::Finish => {
+                    let (s1, n1) = self.current;
+                    match self.runs.pop() {
+                        Some(top) => {
+                            self.work.push(Work::Merge(s1, s1 + n1, top.end + 1));
+                            self.current = (s1, n1 + top.length);
+                        }
+                        None => self.state = State::Done,
+                    }
+                }
+                State::Done => {}
+            }
+        }
+
+        self.comparisons += used.get();
+        if self.is_done() { Progress::Done } else { Progress::Pending }
+    }
+}
+
+/// The state after finding a run starting at `start`.
+fn next_scan(start: usize) -> State {
+    if start == 0 {
+        State::Finish
+    } else {
+        State::Scan { end: start - 1, start: start - 1, increasing: None }
+    }
+}
+
+#[cfg(test)]
+mod incremental_power_sort_tests {
+    use super::{IncrementalPowerSort, Progress};
+    use crate::powersort_final::power_sort;
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    fn incremental_power_sort_test_1() {
+        let mut v = generate_m_runs(1000, 40);
+        let mut sorted = v.clone();
+        sorted.sort();
+
+        IncrementalPowerSort::new(&mut v, |a, b| a < b).finish();
+        assert_eq!(v, sorted);
+    }
+
+    #[test]
+    // Tiny budgets still make progress, keep the slice a permutation between steps, and end
+    // with the same (stable) result as power_sort.
+    fn incremental_power_sort_test_2() {
+        let keys = generate_random_sequence_in_range(500, 0, 30);
+        let input: Vec<(i32, usize)> = keys.into_iter().zip(0..).collect();
+        let mut expected = input.clone();
+        power_sort(&mut expected, |a, b| a.0 < b.0);
+
+        for budget in [1, 2, 5, 64, 1000] {
+            let mut v = input.clone();
+            let mut steps = vec![];
+            {
+                let mut sort = IncrementalPowerSort::new(&mut v, |a: &(i32, usize), b: &(i32, usize)| a.0 < b.0);
+                while sort.step(budget) == Progress::Pending {
+                    let mut snapshot: Vec<usize> = sort.v.iter().map(|x| x.1).collect();
+                    snapshot.sort();
+                    steps.push(snapshot);
+                }
+            }
+            assert!(steps.iter().all(|s| s.iter().copied().eq(0..500)));
+            assert_eq!(v, expected);
+        }
+    }
+
+    #[test]
+    fn incremental_power_sort_test_3() {
+        let mut v: Vec<i32> = vec![];
+        assert_eq!(IncrementalPowerSort::new(&mut v, |a, b| a < b).step(10), Progress::Done);
+
+        let mut v = vec![2, 1];
+        let mut sort = IncrementalPowerSort::new(&mut v, |a, b| a < b);
+        assert_eq!(sort.step(0), Progress::Pending);
+        assert_eq!(sort.step(10), Progress::Done);
+        assert_eq!(sort.comparisons(), 1);
+        assert_eq!(v, vec![1, 2]);
+    }
+
+    #[test]
+    // Rotations are budgeted, so a small step can't move many elements at once.
+    fn incremental_power_sort_test_4() {
+        let mut v: Vec<i32> = (0..300).map(|x| 2 * x + 1).chain((0..300).map(|x| 2 * x)).collect();
+        let mut sort = IncrementalPowerSort::new(&mut v, |a, b| a < b);
+
+        let mut before = sort.v.to_vec();
+        while sort.step(4) == Progress::Pending {
+            let changed = before.iter().zip(sort.v.iter()).filter(|(x, y)| x != y).count();
+            assert!(changed <= 16, "a step moved {} elements", changed);
+            before.copy_from_slice(sort.v);
+        }
+        assert!(v.iter().copied().eq(0..600));
+    }
+}
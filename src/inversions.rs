@@ -0,0 +1,214 @@
+//! Counting inversions, and the Kendall rank correlation built on them.
+
+use std::ops::Range;
+
+use crate::powersort::extend_run_right;
+use crate::powersort_final::{power_sort, RunStack, MIN_RUN_LENGTH};
+
+/// Returns the number of pairs of equal elements in the sorted slice `v`.
+fn tied_pairs<X, E>(v: &[X], eq: E) -> u64
+where
+    E: FnMut(&X, &X) -> bool,
+{
+    v.chunk_by(eq).map(|group| (group.len() * (group.len() - 1) / 2) as u64).sum()
+}
+
+/// Finds the run starting at `start`, makes it weakly increasing and extends it to the minimum run
+/// length using insertion sort. Returns the length of the run and the number of inversions fixed
+/// on the way.
+fn find_run<T: Ord>(v: &mut [&T], start: usize) -> (usize, u64) {
+    let n = v.len();
+    let (mut length, is_increasing) = extend_run_right(v, start, &mut |a, b| a < b);
+    let mut inversions = 0;
+
+    // A strictly decreasing run is inverted in every pair of its elements.
+    if !is_increasing {
+        v[start..start + length].reverse();
+        inversions += (length * (length - 1) / 2) as u64;
+    }
+
+    while length < MIN_RUN_LENGTH && start + length < n {
+        // The next element is inverted with every element of the run greater than it.
+        let x = v[start + length];
+        let position = v[start..start + length].partition_point(|y| *y <= x);
+        v[start + position..=start + length].rotate_right(1);
+        inversions += (length - position) as u64;
+        length += 1;
+    }
+
+    (length, inversions)
+}
+
+/// Merges the runs `v[..mid]` and `v[mid..]` using `buf` as temporary storage, and returns the
+/// number of inversions between them.
+///
+/// Every time an element of the right run goes before the rest of the left run, it's inverted
+/// with every element left in it.
+fn merge_counting<'a, T: Ord>(v: &mut [&'a T], mid: usize, buf: &mut Vec<&'a T>) -> u64 {
+    buf.clear();
+    buf.extend_from_slice(&v[..mid]);
+
+    let (mut i, mut j, mut k) = (0, mid, 0);
+    let mut inversions = 0;
+
+    while i < mid && j < v.len() {
+        // If equal, prefer the left run, equal elements are not inverted.
+        if v[j] < buf[i] {
+            v[k] = v[j];
+            j += 1;
+            inversions += (mid - i) as u64;
+        } else {
+            v[k] = buf[i];
+            i += 1;
+        }
+        k += 1;
+    }
+
+    // The rest of the right run is already in place.
+    v[k..k + mid - i].copy_from_slice(&buf[i..]);
+
+    inversions
+}
+
+/// Sorts `v` with PowerSort, and returns the number of inversions it had.
+fn sort_counting<T: Ord>(v: &mut [&T]) -> u64 {
+    let n = v.len();
+    if n < 2 {
+        return 0;
+    }
+
+    let mut buf = Vec::with_capacity(n / 2);
+    let mut inversions = 0;
+
+    // Stack for storing runs.
+    let mut runs: RunStack<Range<usize>> = RunStack::new(n);
+
+    // Look for runs and merge if possible.
+    let mut s1 = 0;
+    while s1 < n {
+        let (n1, run_inversions) = find_run(v, s1);
+        inversions += run_inversions;
+        runs.push(s1..s1 + n1, n1, |left, right| {
+            inversions += merge_counting(&mut v[left.start..right.end], left.len(), &mut buf);
+            left.end = right.end;
+        });
+        s1 += n1;
+    }
+
+    // Merge remaining runs.
+    runs.finish(|left, right| {
+        inversions += merge_counting(&mut v[left.start..right.end], left.len(), &mut buf);
+        left.end = right.end;
+    });
+
+    inversions
+}
+
+/// Returns the number of inversions in `v`, the pairs `i < j` with `v[j] < v[i]`.
+///
+/// The inversions are counted while sorting references to the elements with PowerSort: a strictly
+/// decreasing run of length `k` has `k * (k - 1) / 2` of them, and the others are counted inside
+/// the merges. This takes *O*(*n* \* log(*n*)) time, and *O*(*n*) on presorted inputs with few
+/// runs. `v` is left untouched.
+pub fn count_inversions<T: Ord>(v: &[T]) -> u64 {
+    let mut refs: Vec<&T> = v.iter().collect();
+    sort_counting(&mut refs)
+}
+
+/// Returns the Kendall rank correlation of `a` and `b`, which are paired by index.
+///
+/// This is the tau-b coefficient, which accounts for ties: 1 if both rank the elements in the same
+/// order, -1 if in opposite orders. It's computed with Knight's algorithm, which sorts the pairs
+/// by `a` and counts the inversions of `b` in that order, in *O*(*n* \* log(*n*)) time.
+///
+/// Returns `None` if there are less than two pairs, or if all elements of `a` or of `b` are equal.
+/// # Panics
+/// Panics if `a` and `b` have different lengths.
+pub fn kendall_tau<T: Ord, U: Ord>(a: &[T], b: &[U]) -> Option<f64> {
+    assert_eq!(a.len(), b.len(), "both rankings must have the same length");
+
+    let n = a.len();
+    if n < 2 {
+        return None;
+    }
+
+    let mut pairs: Vec<(&T, &U)> = a.iter().zip(b).collect();
+    power_sort(&mut pairs, |x, y| x < y);
+
+    let total = (n * (n - 1) / 2) as u64;
+    let ties_a = tied_pairs(&pairs, |x, y| x.0 == y.0);
+    let ties_both = tied_pairs(&pairs, |x, y| x == y);
+
+    // Pairs ordered differently by `a` and `b` are the inversions of `b` once sorted by `a`.
+    let mut ranked: Vec<&U> = pairs.iter().map(|x| x.1).collect();
+    let discordant = sort_counting(&mut ranked);
+    let ties_b = tied_pairs(&ranked, |x, y| x == y);
+
+    if ties_a == total || ties_b == total {
+        return None;
+    }
+
+    let difference = (total + ties_both) as f64 - (ties_a + ties_b) as f64 - 2.0 * discordant as f64;
+    Some(difference / (((total - ties_a) as f64) * ((total - ties_b) as f64)).sqrt())
+}
+
+#[cfg(test)]
+mod inversions_tests {
+    use super::{count_inversions, kendall_tau};
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    fn brute_force_inversions(v: &[i32]) -> u64 {
+        (0..v.len()).map(|i| v[i + 1..].iter().filter(|&&x| x < v[i]).count() as u64).sum()
+    }
+
+    // Tau-b from its definition, comparing every pair.
+    fn brute_force_tau(a: &[i32], b: &[i32]) -> f64 {
+        let (mut concordant, mut discordant, mut ties_a, mut ties_b) = (0.0, 0.0, 0.0, 0.0);
+        for i in 0..a.len() {
+            for j in i + 1..a.len() {
+                let product = (a[i] - a[j]).signum() * (b[i] - b[j]).signum();
+                match product {
+                    1 => concordant += 1.0,
+                    -1 => discordant += 1.0,
+                    _ => {
+                        ties_a += f64::from(u8::from(a[i] == a[j]));
+                        ties_b += f64::from(u8::from(b[i] == b[j]));
+                    }
+                }
+            }
+        }
+        // Pairs tied in both count in both tie counts.
+        let total = (a.len() * (a.len() - 1) / 2) as f64;
+        (concordant - discordant) / ((total - ties_a) * (total - ties_b)).sqrt()
+    }
+
+    #[test]
+    fn count_inversions_test_1() {
+        for v in [generate_random_sequence_in_range(800, 0, 50), generate_m_runs(1000, 30), (0..300).rev().collect()] {
+            assert_eq!(count_inversions(&v), brute_force_inversions(&v));
+        }
+        assert_eq!(count_inversions::<i32>(&[]), 0);
+        assert_eq!(count_inversions(&[3, 3, 3]), 0);
+    }
+
+    #[test]
+    fn kendall_tau_test_1() {
+        let a: Vec<i32> = (0..100).collect();
+        let reversed: Vec<i32> = (0..100).rev().collect();
+        assert_eq!(kendall_tau(&a, &a), Some(1.0));
+        assert_eq!(kendall_tau(&a, &reversed), Some(-1.0));
+        assert_eq!(kendall_tau(&a, &[7; 100]), None);
+        assert_eq!(kendall_tau(&[1], &[2]), None);
+    }
+
+    #[test]
+    // Rankings with many ties match the definition.
+    fn kendall_tau_test_2() {
+        let a = generate_random_sequence_in_range(400, 0, 10);
+        let b: Vec<i32> = a.iter().zip(generate_random_sequence_in_range(400, 0, 6)).map(|(x, y)| x / 2 + y).collect();
+
+        let tau = kendall_tau(&a, &b).unwrap();
+        assert!((tau - brute_force_tau(&a, &b)).abs() < 1e-12);
+        assert!(tau > 0.0);
+    }
+}
@@ -0,0 +1,282 @@
+//! Sort-merge joins of two record sets on a key.
+
+use std::iter::FusedIterator;
+
+use crate::merge::gallop_from_start;
+use crate::powersort_final::power_sort;
+
+/// Which records a [JoinIter] returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JoinKind {
+    Inner,
+    LeftOuter,
+    FullOuter,
+}
+
+/// Two record sets sorted by their keys, ready to be joined. Created by [merge_join].
+pub struct MergeJoin<'a, L, R, KL, KR> {
+    left: &'a [L],
+    right: &'a [R],
+    key_l: KL,
+    key_r: KR,
+}
+
+/// Sorts `left` and `right` by their keys and returns a [MergeJoin] to stream their join.
+///
+/// Each side is sorted with [power_sort], unless it's already sorted, in which case checking it
+/// takes a single pass. The sorts are stable, so records with equal keys are joined in their
+/// original order.
+pub fn merge_join<'a, L, R, K, KL, KR>(
+    left: &'a mut [L],
+    right: &'a mut [R],
+    key_l: KL,
+    key_r: KR,
+) -> MergeJoin<'a, L, R, KL, KR>
+where
+    K: Ord,
+    KL: Fn(&L) -> K,
+    KR: Fn(&R) -> K,
+{
+    if !left.windows(2).all(|w| key_l(&w[0]) <= key_l(&w[1])) {
+        power_sort(left, |a, b| key_l(a) < key_l(b));
+    }
+    if !right.windows(2).all(|w| key_r(&w[0]) <= key_r(&w[1])) {
+        power_sort(right, |a, b| key_r(a) < key_r(b));
+    }
+
+    MergeJoin { left, right, key_l, key_r }
+}
+
+impl<'a, L, R, K, KL, KR> MergeJoin<'a, L, R, KL, KR>
+where
+    K: Ord,
+    KL: Fn(&L) -> K,
+    KR: Fn(&R) -> K,
+{
+    fn iter(self, kind: JoinKind) -> JoinIter<'a, L, R, KL, KR> {
+        JoinIter {
+            left: self.left,
+            right: self.right,
+            key_l: self.key_l,
+            key_r: self.key_r,
+            kind,
+            group: None,
+        }
+    }
+
+    /// Returns every pair of records with equal keys.
+    pub fn inner(self) -> impl Iterator<Item = (&'a L, &'a R)> {
+        self.iter(JoinKind::Inner).map(|(l, r)| (l.unwrap(), r.unwrap()))
+    }
+
+    /// Returns every pair of records with equal keys, and every left record without a match paired
+    /// with `None`.
+    pub fn left_outer(self) -> impl Iterator<Item = (&'a L, Option<&'a R>)> {
+        self.iter(JoinKind::LeftOuter).map(|(l, r)| (l.unwrap(), r))
+    }
+
+    /// Returns every pair of records with equal keys, and every record of either side without a
+    /// match paired with `None`.
+    pub fn full_outer(self) -> JoinIter<'a, L, R, KL, KR> {
+        self.iter(JoinKind::FullOuter)
+    }
+}
+
+/// An iterator over the join of two sorted record sets, in key order.
+///
+/// Records with equal keys form a group on each side, and the cross product of two matching groups
+/// is returned left record by left record. Unmatched records that are not part of the result are
+/// skipped by galloping.
+pub struct JoinIter<'a, L, R, KL, KR> {
+    left: &'a [L],
+    right: &'a [R],
+    key_l: KL,
+    key_r: KR,
+    kind: JoinKind,
+    // Two matching groups, and the position of the next pair in their cross product.
+    group: Option<(&'a [L], &'a [R], usize, usize)>,
+}
+
+impl<'a, L, R, K, KL, KR> Iterator for JoinIter<'a, L, R, KL, KR>
+where
+    K: Ord,
+    KL: Fn(&L) -> K,
+    KR: Fn(&R) -> K,
+{
+    type Item = (Option<&'a L>, Option<&'a R>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key_l, key_r) = (&self.key_l, &self.key_r);
+        let keeps_left = self.kind != JoinKind::Inner;
+        let keeps_right = self.kind == JoinKind::FullOuter;
+
+        loop {
+            if let Some((lg, rg, p, q)) = &mut self.group {
+                if *p < lg.len() {
+                    let item = (Some(&lg[*p]), Some(&rg[*q]));
+                    *q += 1;
+                    if *q == rg.len() {
+                        *q = 0;
+                        *p += 1;
+                    }
+                    return Some(item);
+                }
+                self.group = None;
+            }
+
+            let (left, right) = (self.left, self.right);
+            match (left.first(), right.first()) {
+                (None, None) => return None,
+                (Some(l), None) => {
+                    if !keeps_left {
+                        self.left = &[];
+                        return None;
+                    }
+                    self.left = &left[1..];
+                    return Some((Some(l), None));
+                }
+                (None, Some(r)) => {
+                    if !keeps_right {
+                        self.right = &[];
+                        return None;
+                    }
+                    self.right = &right[1..];
+                    return Some((None, Some(r)));
+                }
+                (Some(l), Some(r)) => {
+                    let (kl, kr) = (key_l(l), key_r(r));
+                    if kl < kr {
+                        if keeps_left {
+                            self.left = &left[1..];
+                            return Some((Some(l), None));
+                        }
+                        self.left = &left[gallop_from_start(left, |x| key_l(x) < kr)..];
+                    } else if kr < kl {
+                        if keeps_right {
+                            self.right = &right[1..];
+                            return Some((None, Some(r)));
+                        }
+                        self.right = &right[gallop_from_start(right, |x| key_r(x) < kl)..];
+                    } else {
+                        // Both groups end at the first key greater than this one.
+                        let le = gallop_from_start(left, |x| key_l(x) <= kl);
+                        let re = gallop_from_start(right, |x| key_r(x) <= kr);
+                        self.group = Some((&left[..le], &right[..re], 0, 0));
+                        self.left = &left[le..];
+                        self.right = &right[re..];
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<L, R, K, KL, KR> FusedIterator for JoinIter<'_, L, R, KL, KR>
+where
+    K: Ord,
+    KL: Fn(&L) -> K,
+    KR: Fn(&R) -> K,
+{
+}
+
+#[cfg(test)]
+mod merge_join_tests {
+    use super::merge_join;
+    use crate::sequences::generate_random_sequence_in_range;
+
+    type Left = (i32, usize);
+    type Right = (i32, char);
+    type Joined = Vec<(Option<Left>, Option<Right>)>;
+
+    fn sides() -> (Vec<Left>, Vec<Right>) {
+        let left = generate_random_sequence_in_range(300, 0, 60).into_iter().zip(0..).collect();
+        let right = generate_random_sequence_in_range(200, 20, 80)
+            .into_iter()
+            .zip("abcdefghij".chars().cycle())
+            .collect();
+        (left, right)
+    }
+
+    // The join computed by nested loops over the stably sorted sides.
+    fn expected(left: &[Left], right: &[Right], keep_left: bool, keep_right: bool) -> Joined {
+        let (mut left, mut right) = (left.to_vec(), right.to_vec());
+        left.sort_by_key(|x| x.0);
+        right.sort_by_key(|x| x.0);
+
+        let mut keys: Vec<i32> =
+            left.iter().map(|x| x.0).chain(right.iter().map(|x| x.0)).collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut joined = Vec::new();
+        for k in keys {
+            let ls: Vec<_> = left.iter().filter(|x| x.0 == k).collect();
+            let rs: Vec<_> = right.iter().filter(|x| x.0 == k).collect();
+            match (ls.is_empty(), rs.is_empty()) {
+                (false, false) => joined
+                    .extend(ls.iter().flat_map(|&&l| rs.iter().map(move |&&r| (Some(l), Some(r))))),
+                (false, true) if keep_left => joined.extend(ls.iter().map(|&&l| (Some(l), None))),
+                (true, false) if keep_right => joined.extend(rs.iter().map(|&&r| (None, Some(r)))),
+                _ => {}
+            }
+        }
+        joined
+    }
+
+    #[test]
+    fn merge_join_test_1() {
+        let (left, right) = sides();
+
+        let (mut l, mut r) = (left.clone(), right.clone());
+        let inner: Joined = merge_join(&mut l, &mut r, |x| x.0, |x| x.0)
+            .inner()
+            .map(|(l, r)| (Some(*l), Some(*r)))
+            .collect();
+        assert_eq!(inner, expected(&left, &right, false, false));
+
+        let (mut l, mut r) = (left.clone(), right.clone());
+        let left_outer: Joined = merge_join(&mut l, &mut r, |x| x.0, |x| x.0)
+            .left_outer()
+            .map(|(l, r)| (Some(*l), r.copied()))
+            .collect();
+        assert_eq!(left_outer, expected(&left, &right, true, false));
+
+        let (mut l, mut r) = (left.clone(), right.clone());
+        let full_outer: Joined = merge_join(&mut l, &mut r, |x| x.0, |x| x.0)
+            .full_outer()
+            .map(|(l, r)| (l.copied(), r.copied()))
+            .collect();
+        assert_eq!(full_outer, expected(&left, &right, true, true));
+    }
+
+    #[test]
+    // Duplicate keys on both sides give the cross product of their groups.
+    fn merge_join_test_2() {
+        let mut left = vec![(2, 'x'), (1, 'a'), (1, 'b'), (3, 'y')];
+        let mut right = vec![(1, 10), (1, 20), (1, 30), (4, 40)];
+
+        let inner: Vec<(char, i32)> = merge_join(&mut left, &mut right, |x| x.0, |x| x.0)
+            .inner()
+            .map(|(l, r)| (l.1, r.1))
+            .collect();
+        assert_eq!(inner, vec![('a', 10), ('a', 20), ('a', 30), ('b', 10), ('b', 20), ('b', 30)]);
+        assert_eq!(left, vec![(1, 'a'), (1, 'b'), (2, 'x'), (3, 'y')]);
+    }
+
+    #[test]
+    // Sides that are already sorted are left untouched.
+    fn merge_join_test_3() {
+        let mut left: Vec<i32> = (0..100).collect();
+        let mut right: Vec<i32> = (50..150).step_by(5).collect();
+
+        let calls = std::cell::Cell::new(0);
+        let key = |x: &i32| {
+            calls.set(calls.get() + 1);
+            *x
+        };
+        let count = merge_join(&mut left, &mut right, key, |x| *x).inner().count();
+        assert_eq!(count, 10);
+        // One pass to check the order, then at most a few key computations per element.
+        assert!(calls.get() < 2 * 99 + 4 * 100);
+    }
+}
@@ -0,0 +1,458 @@
+//! Lazy sorting and merging: reading the smallest elements of a sequence, or of many sorted
+//! streams, in order without sorting or merging all of it.
+
+use std::iter::FusedIterator;
+
+use crate::permutation::apply_permutation;
+use crate::powersort::extend_run_right;
+use crate::powersort_final::RunStack;
+
+/// Where the elements of a node of the merge tree come from.
+#[derive(Clone, Copy, Debug)]
+enum Source {
+    /// The run `v[front..back]`, read backwards if it's strictly decreasing.
+    Run { front: usize, back: usize, is_decreasing: bool },
+    /// The merge of two other nodes, the left one holding the earlier elements of `v`.
+    Merge { left: usize, right: usize },
+}
+
+/// A node of the merge tree, with the index of the next element it yields.
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    head: Option<usize>,
+    source: Source,
+}
+
+/// An iterator over the elements of a slice in sorted order, merging its runs lazily.
+///
+/// Created by [power_sorted_iter].
+pub struct PowerSortedIter<'a, T, F> {
+    v: &'a [T],
+    is_less: F,
+    // Children always come before their parent, and the root is the last node.
+    nodes: Vec<Node>,
+    remaining: usize,
+}
+
+impl<'a, T, F> PowerSortedIter<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    fn new(v: &'a [T], is_less: F) -> Self {
+        let mut iter = PowerSortedIter { v, is_less, nodes: Vec::new(), remaining: v.len() };
+        let n = v.len();
+
+        // Stack for storing runs, as the nodes of their merge trees.
+        let mut runs: RunStack<usize> = RunStack::new(n);
+
+        // Look for runs and add a merge node where PowerSort would merge them.
+        let mut s1 = 0;
+        while s1 < n {
+            let n1 = iter.push_run(s1);
+            let node = iter.nodes.len() - 1;
+            runs.push(node, n1, |left, right| *left = iter.push_merge(*left, *right));
+            s1 += n1;
+        }
+
+        // Merge remaining runs.
+        runs.finish(|left, right| *left = iter.push_merge(*left, *right));
+
+        iter
+    }
+
+    /// Adds a leaf for the run starting at `start`, and returns its length.
+    fn push_run(&mut self, start: usize) -> usize {
+        let (length, is_increasing) = extend_run_right(self.v, start, &mut self.is_less);
+        let source = Source::Run { front: start, back: start + length, is_decreasing: !is_increasing };
+        self.nodes.push(Node { head: None, source });
+        self.refill(self.nodes.len() - 1);
+        length
+    }
+
+    /// Adds a node merging `left` and `right`, and returns its index.
+    fn push_merge(&mut self, left: usize, right: usize) -> usize {
+        self.nodes.push(Node { head: None, source: Source::Merge { left, right } });
+        let node = self.nodes.len() - 1;
+        self.refill(node);
+        node
+    }
+
+    /// Moves the next element up into the empty head of `node`, refilling every head it comes from
+    /// on the way down. This takes one comparison per level of the tree.
+    fn refill(&mut self, mut node: usize) {
+        loop {
+            match self.nodes[node].source {
+                Source::Run { front, back, is_decreasing } => {
+                    let head = if front == back {
+                        None
+                    } else if is_decreasing {
+                        Some(back - 1)
+                    } else {
+                        Some(front)
+                    };
+                    let source = match head {
+                        Some(i) if is_decreasing => Source::Run { front, back: i, is_decreasing },
+                        Some(i) => Source::Run { front: i + 1, back, is_decreasing },
+                        None => Source::Run { front, back, is_decreasing },
+                    };
+                    self.nodes[node] = Node { head, source };
+                    return;
+                }
+                Source::Merge { left, right } => {
+                    // If equal, prefer the left node, which holds the earlier elements.
+                    let child = match (self.nodes[left].head, self.nodes[right].head) {
+                        (Some(l), Some(r)) if (self.is_less)(&self.v[r], &self.v[l]) => right,
+                        (Some(_), _) => left,
+                        (None, Some(_)) => right,
+                        (None, None) => {
+                            self.nodes[node].head = None;
+                            return;
+                        }
+                    };
+                    self.nodes[node].head = self.nodes[child].head.take();
+                    node = child;
+                }
+            }
+        }
+    }
+
+    /// Returns the index in `v` of the next element.
+    fn next_index(&mut self) -> Option<usize> {
+        let root = self.nodes.len().checked_sub(1)?;
+        let head = self.nodes[root].head.take()?;
+        self.refill(root);
+        self.remaining -= 1;
+        Some(head)
+    }
+}
+
+impl<'a, T, F> Iterator for PowerSortedIter<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let v = self.v;
+        self.next_index().map(|i| &v[i])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T, F> ExactSizeIterator for PowerSortedIter<'_, T, F> where F: FnMut(&T, &T) -> bool {}
+
+impl<T, F> FusedIterator for PowerSortedIter<'_, T, F> where F: FnMut(&T, &T) -> bool {}
+
+/// Returns an iterator over the elements of `v` in sorted order, without sorting it.
+///
+/// The runs of `v` are found with [extend_run_right] and become the leaves of the tree of merges
+/// PowerSort would do, shaped by [node_power](crate::powersort_final::node_power). Nothing is
+/// merged up front: every node only holds its next element, and taking one refills the path it
+/// came from. Finding the runs takes *O*(*n*) time, and each element read costs one comparison per
+/// level of the tree, which is *O*(log(*r*)) for `r` balanced runs and never more than
+/// *O*(log(*n*)).
+///
+/// Equal elements are returned in their original order.
+pub fn power_sorted_iter<T, F>(v: &[T], is_less: F) -> PowerSortedIter<'_, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    PowerSortedIter::new(v, is_less)
+}
+
+/// Rearranges `v` so that `v[..k]` holds its `k` smallest elements in sorted order. The rest are
+/// left in their original relative order.
+///
+/// The first `k` elements are read from [power_sorted_iter], so this takes *O*(*n* + *k* \*
+/// log(*r*)) comparisons for `r` runs, and *O*(*n*) extra memory. The sort is stable. If `k` is at
+/// least `v.len()`, all of `v` gets sorted.
+pub fn power_partial_sort<T, F>(v: &mut [T], k: usize, is_less: F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let n = v.len();
+    let mut iter = PowerSortedIter::new(v, is_less);
+    let mut perm: Vec<usize> = Vec::with_capacity(n);
+    let mut taken = vec![false; n];
+
+    while perm.len() < k {
+        let Some(i) = iter.next_index() else { break };
+        taken[i] = true;
+        perm.push(i);
+    }
+
+    perm.extend((0..n).filter(|&i| !taken[i]));
+    apply_permutation(v, &mut perm);
+}
+
+/// Where the elements of a node of a [MergeSortedIters] tree come from.
+#[derive(Clone, Copy, Debug)]
+enum StreamSource {
+    /// The stream with this index.
+    Stream(usize),
+    /// The merge of two other nodes, the left one holding the earlier streams.
+    Merge { left: usize, right: usize },
+}
+
+/// A node of a [MergeSortedIters] tree, with the next element it yields.
+#[derive(Clone, Copy, Debug)]
+struct StreamNode<T> {
+    head: Option<T>,
+    source: StreamSource,
+}
+
+/// An iterator merging many sorted iterators lazily, through a tree of pairwise merges.
+///
+/// Created by [merge_sorted_iters].
+pub struct MergeSortedIters<I: Iterator, F> {
+    streams: Vec<I>,
+    is_less: F,
+    // Children always come before their parent, and the root is the last node.
+    nodes: Vec<StreamNode<I::Item>>,
+}
+
+impl<I, F> MergeSortedIters<I, F>
+where
+    I: Iterator,
+    F: FnMut(&I::Item, &I::Item) -> bool,
+{
+    fn new(iters: Vec<(I, Option<usize>)>, is_less: F) -> Self {
+        // Without every size, all streams weigh the same and the tree is a balanced tournament.
+        // Empty streams still weigh one, since powers are only defined for non-empty runs.
+        let weights: Vec<usize> = if iters.iter().all(|(_, size)| size.is_some()) {
+            iters.iter().map(|(_, size)| size.unwrap().max(1)).collect()
+        } else {
+            vec![1; iters.len()]
+        };
+        let n = weights.iter().sum();
+
+        let streams = iters.into_iter().map(|(iter, _)| iter).collect();
+        let mut merged =
+            MergeSortedIters { streams, is_less, nodes: Vec::with_capacity(2 * weights.len()) };
+
+        // Stack for storing runs, as the nodes of their merge trees.
+        let mut runs: RunStack<usize> = RunStack::new(n);
+
+        // Add a merge node where PowerSort would merge two runs of these lengths.
+        for (i, &weight) in weights.iter().enumerate() {
+            let node = merged.push_node(StreamSource::Stream(i));
+            runs.push(node, weight, |left, right| {
+                *left = merged.push_node(StreamSource::Merge { left: *left, right: *right });
+            });
+        }
+
+        // Merge remaining runs.
+        runs.finish(|left, right| {
+            *left = merged.push_node(StreamSource::Merge { left: *left, right: *right });
+        });
+
+        merged
+    }
+
+    /// Adds a node taking its elements from `source`, and returns its index.
+    fn push_node(&mut self, source: StreamSource) -> usize {
+        self.nodes.push(StreamNode { head: None, source });
+        let node = self.nodes.len() - 1;
+        self.refill(node);
+        node
+    }
+
+    /// Moves the next element up into the empty head of `node`, refilling every head it comes from
+    /// on the way down. This takes one comparison per level of the tree.
+    fn refill(&mut self, mut node: usize) {
+        loop {
+            match self.nodes[node].source {
+                StreamSource::Stream(i) => {
+                    self.nodes[node].head = self.streams[i].next();
+                    return;
+                }
+                StreamSource::Merge { left, right } => {
+                    // If equal, prefer the left node, which holds the earlier streams.
+                    let child = match (&self.nodes[left].head, &self.nodes[right].head) {
+                        (Some(l), Some(r)) if (self.is_less)(r, l) => right,
+                        (Some(_), _) => left,
+                        (None, Some(_)) => right,
+                        (None, None) => return,
+                    };
+                    self.nodes[node].head = self.nodes[child].head.take();
+                    node = child;
+                }
+            }
+        }
+    }
+}
+
+impl<I, F> Iterator for MergeSortedIters<I, F>
+where
+    I: Iterator,
+    F: FnMut(&I::Item, &I::Item) -> bool,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let root = self.nodes.len().checked_sub(1)?;
+        let head = self.nodes[root].head.take()?;
+        self.refill(root);
+        Some(head)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let buffered = self.nodes.iter().filter(|node| node.head.is_some()).count();
+        self.streams.iter().fold((buffered, Some(buffered)), |(lower, upper), stream| {
+            let (l, u) = stream.size_hint();
+            (lower.saturating_add(l), upper.zip(u).and_then(|(a, b)| a.checked_add(b)))
+        })
+    }
+}
+
+impl<I, F> FusedIterator for MergeSortedIters<I, F>
+where
+    I: FusedIterator,
+    F: FnMut(&I::Item, &I::Item) -> bool,
+{
+}
+
+/// Merges the sorted iterators in `iters` into a single sorted iterator, lazily.
+///
+/// Every stream comes with its number of elements, if known, and the streams are merged pairwise
+/// through the tree PowerSort would build for runs of those lengths, shaped by
+/// [node_power](crate::powersort_final::node_power). Large streams end up near the root, so their
+/// elements go through fewer comparisons than with a flat heap, and the total cost is within a
+/// constant of the entropy of the stream sizes. If any size is `None`, every stream weighs the
+/// same and the tree is a balanced tournament tree instead.
+///
+/// Nodes hold one element each, so at most one element per stream is buffered and each element
+/// costs one comparison per level of the tree above its stream. Equal elements are returned in the
+/// order of their streams.
+pub fn merge_sorted_iters<I, F>(iters: Vec<(I, Option<usize>)>, is_less: F) -> MergeSortedIters<I, F>
+where
+    I: Iterator,
+    F: FnMut(&I::Item, &I::Item) -> bool,
+{
+    MergeSortedIters::new(iters, is_less)
+}
+
+#[cfg(test)]
+mod power_sorted_iter_tests {
+    use super::{power_partial_sort, power_sorted_iter};
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    fn power_sorted_iter_test_1() {
+        for v in [generate_random_sequence_in_range(2000, 0, 100), generate_m_runs(3000, 25), (0..500).rev().collect(), vec![]] {
+            let mut expected = v.clone();
+            expected.sort();
+
+            let iter = power_sorted_iter(&v, |a, b| a < b);
+            assert_eq!(iter.len(), v.len());
+            assert!(iter.copied().eq(expected));
+        }
+    }
+
+    #[test]
+    // Reading a few elements of a long sequence with few runs takes few comparisons.
+    fn power_sorted_iter_test_2() {
+        // Sixteen sorted runs, interleaved with each other.
+        let v: Vec<i32> = (0..16).flat_map(|r| (0..6250).map(move |i| i * 16 + r)).collect();
+
+        let mut comparisons = 0;
+        let first: Vec<i32> = power_sorted_iter(&v, |a, b| {
+            comparisons += 1;
+            a < b
+        })
+        .take(10)
+        .copied()
+        .collect();
+
+        assert!(first.into_iter().eq(0..10));
+        // Finding the runs, filling the tree of depth 4, then a path per element read.
+        assert!(comparisons < 100_000 + 16 * 4 + 10 * 4);
+    }
+
+    #[test]
+    // Equal elements keep their original order.
+    fn power_partial_sort_test_1() {
+        let keys = generate_random_sequence_in_range(1000, 0, 40);
+        let values: Vec<(i32, usize)> = keys.into_iter().zip(0..).collect();
+        let mut expected = values.clone();
+        expected.sort_by_key(|x| x.0);
+
+        for k in [0, 1, 37, 1000, 2000] {
+            let mut v = values.clone();
+            power_partial_sort(&mut v, k, |a, b| a.0 < b.0);
+
+            let k = k.min(v.len());
+            assert_eq!(v[..k], expected[..k]);
+            let rest: Vec<_> = values.iter().filter(|x| !v[..k].contains(x)).collect();
+            assert!(v[k..].iter().eq(rest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod merge_sorted_iters_tests {
+    use std::cell::Cell;
+
+    use super::merge_sorted_iters;
+    use crate::sequences::generate_random_sequence_in_range;
+
+    type Stream = std::vec::IntoIter<(i32, usize)>;
+
+    // Sorted streams of skewed sizes, each element tagged with its stream.
+    fn streams() -> Vec<Vec<(i32, usize)>> {
+        [3000, 1, 0, 40, 500, 7, 7, 1200, 0, 90]
+            .into_iter()
+            .enumerate()
+            .map(|(i, length)| {
+                let mut v = generate_random_sequence_in_range(length, 0, 200);
+                v.sort();
+                v.into_iter().map(|x| (x, i)).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_sorted_iters_test_1() {
+        let streams = streams();
+        let mut expected: Vec<(i32, usize)> = streams.concat();
+        expected.sort_by_key(|x| x.0);
+
+        for known in [true, false] {
+            let iters: Vec<(Stream, Option<usize>)> =
+                streams.iter().map(|s| (s.clone().into_iter(), known.then_some(s.len()))).collect();
+            let merged = merge_sorted_iters(iters, |a, b| a.0 < b.0);
+            assert_eq!(merged.size_hint(), (expected.len(), Some(expected.len())));
+            assert!(merged.eq(expected.iter().copied()));
+        }
+
+        assert_eq!(merge_sorted_iters(Vec::<(Stream, Option<usize>)>::new(), |a, b| a < b).next(), None);
+    }
+
+    #[test]
+    // With known sizes, a huge stream goes through fewer comparisons than with a balanced tree.
+    fn merge_sorted_iters_test_2() {
+        let mut streams: Vec<Vec<i32>> = vec![(0..100_000).collect()];
+        streams.extend((0..127).map(|i| (0..10).map(|x| x * 10_000 + i).collect()));
+        let mut expected = streams.concat();
+        expected.sort();
+        let total = expected.len();
+
+        let count = |known: bool| {
+            let comparisons = Cell::new(0);
+            let iters = streams.iter().map(|s| (s.iter(), known.then_some(s.len()))).collect();
+            let merged = merge_sorted_iters(iters, |a, b| {
+                comparisons.set(comparisons.get() + 1);
+                a < b
+            });
+            assert!(merged.eq(expected.iter()));
+            comparisons.get()
+        };
+
+        // The tournament tree has depth 7, the big stream sits right below the root of the other.
+        assert!(count(false) > 6 * total);
+        assert!(count(true) < 2 * total);
+    }
+}
@@ -4,12 +4,32 @@ pub mod alternatives;
 pub mod powersort_alternatives;
 pub mod powersort;
 pub mod powersort_final;
+pub mod error;
+pub mod checked;
+pub mod control;
+pub mod incremental;
+pub mod floats;
+pub mod permutation;
+pub mod strings;
+pub mod split;
+pub mod list;
+pub mod target;
+pub mod rows;
+pub mod compare;
+pub mod merge;
+pub mod sorted_ops;
+pub mod join;
+pub mod combine;
+pub mod partition;
+pub mod inversions;
+pub mod lazy;
+pub mod sorted_vec;
+pub mod lsm;
 
 #[cfg(test)]
 mod demonstrations {
     use rand::prelude::*;
     use rand_distr::Uniform;
-    use rand::seq::SliceRandom;
 
     use crate::sequences::generate_random_sequence;
 
@@ -59,7 +79,7 @@ mod demonstrations {
 
         impl PartialOrd for Chip {
             fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-                Some(self.value.cmp(&other.value))
+                Some(self.cmp(other))
             }
         }
 
@@ -0,0 +1,235 @@
+use std::collections::LinkedList;
+use std::mem;
+
+use crate::powersort_final::RunStack;
+
+/// Returns the length of the run at the front of `list`, and whether it is weakly increasing.
+/// See [extend_run_right](crate::powersort::extend_run_right).
+fn run_length<T, F>(list: &LinkedList<T>, is_less: &mut F) -> (usize, bool)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let mut iter = list.iter();
+    let (mut previous, current) = match (iter.next(), iter.next()) {
+        (Some(first), Some(second)) => (first, second),
+        (first, _) => return (first.is_some() as usize, true),
+    };
+
+    let is_increasing = !is_less(current, previous);
+    let mut length = 2;
+    previous = current;
+
+    for current in iter {
+        // Weakly increasing runs stop at a descent, strictly decreasing runs at anything else.
+        if is_less(current, previous) == is_increasing {
+            break;
+        }
+        length += 1;
+        previous = current;
+    }
+
+    (length, is_increasing)
+}
+
+/// Reverses `list` by relinking its nodes.
+fn reverse<T>(list: &mut LinkedList<T>) {
+    let mut reversed = LinkedList::new();
+    while !list.is_empty() {
+        // Splitting off the last node only walks one step from the back.
+        let mut last = list.split_off(list.len() - 1);
+        reversed.append(&mut last);
+    }
+    *list = reversed;
+}
+
+/// Detaches the run at the front of `rest` and makes it weakly increasing.
+fn next_run<T, F>(rest: &mut LinkedList<T>, is_less: &mut F) -> LinkedList<T>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (length, is_increasing) = run_length(rest, is_less);
+    let tail = rest.split_off(length);
+    let mut run = mem::replace(rest, tail);
+
+    if !is_increasing {
+        reverse(&mut run);
+    }
+    run
+}
+
+/// Merges the sorted lists `left` and `right` by relinking their nodes, appending the result to
+/// `merged` and leaving both empty.
+///
+/// Instead of moving one node at a time, the longest block of one list that goes before the front
+/// of the other is found and moved with a single split. The block taken next always comes from
+/// the other list, so every element is compared about once.
+fn merge_lists<T, F>(
+    merged: &mut LinkedList<T>,
+    left: &mut LinkedList<T>,
+    right: &mut LinkedList<T>,
+    is_less: &mut F,
+) where
+    F: FnMut(&T, &T) -> bool,
+{
+    let mut from_right = match (left.front(), right.front()) {
+        (Some(l), Some(r)) => is_less(r, l),
+        _ => false,
+    };
+
+    while let (Some(l), Some(r)) = (left.front(), right.front()) {
+        // If equal, prefer the left list to maintain stability.
+        let (source, count) = if from_right {
+            let count = right.iter().take_while(|x| is_less(x, l)).count();
+            (&mut *right, count)
+        } else {
+            let count = left.iter().take_while(|x| !is_less(r, x)).count();
+            (&mut *left, count)
+        };
+
+        let rest = source.split_off(count);
+        merged.append(source);
+        *source = rest;
+        from_right = !from_right;
+    }
+
+    merged.append(left);
+    merged.append(right);
+}
+
+/// Holds the nodes of a list while it is sorted, and links them all back into it when dropped.
+///
+/// Every node is in exactly one of the fields at any time, so if `is_less` panics the list gets
+/// back the same elements, in some order.
+struct Nodes<'a, T> {
+    list: &'a mut LinkedList<T>,
+    // Stack for storing runs.
+    runs: RunStack<LinkedList<T>>,
+    // The result of the merge in progress.
+    merged: LinkedList<T>,
+    // The elements after the runs found so far.
+    rest: LinkedList<T>,
+}
+
+impl<T> Drop for Nodes<'_, T> {
+    fn drop(&mut self) {
+        for run in self.runs.iter_mut() {
+            self.list.append(run);
+        }
+        self.list.append(&mut self.merged);
+        self.list.append(&mut self.rest);
+    }
+}
+
+/// Sorts a `LinkedList` with PowerSort by relinking its nodes.
+///
+/// Natural runs are detached from the front of the list and merged in
+/// [node_power](crate::powersort_final::node_power) order, like
+/// [power_sort](crate::powersort::power_sort) does for slices. No element is moved and no memory
+/// proportional to the length of the list is allocated: runs are split off, reversed and merged
+/// with `split_off` and `append`, and the stack of pending runs has logarithmic size.
+///
+/// The sort is stable. If `is_less` panics, the list is left with the same elements in an
+/// unspecified order.
+pub fn power_sort_list<T, F>(list: &mut LinkedList<T>, mut is_less: F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let n = list.len();
+    if n < 2 {
+        return;
+    }
+
+    let rest = mem::take(list);
+    let mut nodes = Nodes { list, runs: RunStack::new(n), merged: LinkedList::new(), rest };
+
+    // Look for runs and merge if possible.
+    while !nodes.rest.is_empty() {
+        let run = next_run(&mut nodes.rest, &mut is_less);
+        let length = run.len();
+        nodes.runs.push(run, length, |left, right| {
+            merge_lists(&mut nodes.merged, left, right, &mut is_less);
+            mem::swap(left, &mut nodes.merged);
+        });
+    }
+
+    // Merge remaining runs.
+    nodes.runs.collapse(|left, right| {
+        merge_lists(&mut nodes.merged, left, right, &mut is_less);
+        mem::swap(left, &mut nodes.merged);
+    });
+
+    // Dropping `nodes` links the sorted run back into `list`.
+}
+
+#[cfg(test)]
+mod power_sort_list_tests {
+    use std::collections::LinkedList;
+
+    use super::power_sort_list;
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    fn power_sort_list_test_1() {
+        let values = generate_m_runs(1000, 40);
+        let mut list: LinkedList<i32> = values.iter().copied().collect();
+        let mut sorted = values;
+        sorted.sort();
+
+        power_sort_list(&mut list, |a, b| a < b);
+        assert!(list.iter().eq(sorted.iter()));
+    }
+
+    #[test]
+    // Equal elements keep their order, and every element stays in the node it started in.
+    fn power_sort_list_test_2() {
+        let keys = generate_random_sequence_in_range(500, 0, 20);
+        let mut list: LinkedList<(i32, usize)> = keys.into_iter().zip(0..).collect();
+        let mut addresses = vec![std::ptr::null(); list.len()];
+        for x in list.iter() {
+            addresses[x.1] = x as *const (i32, usize);
+        }
+        let mut sorted: Vec<(i32, usize)> = list.iter().copied().collect();
+        sorted.sort_by_key(|x| x.0);
+
+        power_sort_list(&mut list, |a, b| a.0 < b.0);
+
+        assert!(list.iter().eq(sorted.iter()));
+        assert!(list.iter().all(|x| std::ptr::eq(addresses[x.1], x)));
+    }
+
+    #[test]
+    fn power_sort_list_test_3() {
+        let mut list: LinkedList<i32> = LinkedList::new();
+        power_sort_list(&mut list, |a, b| a < b);
+        assert!(list.is_empty());
+
+        let mut list: LinkedList<i32> = (0..50).rev().collect();
+        power_sort_list(&mut list, |a, b| a < b);
+        assert!(list.into_iter().eq(0..50));
+    }
+
+    #[test]
+    // A comparison panicking partway through leaves the list with the same elements.
+    fn power_sort_list_test_4() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let values = generate_m_runs(1000, 40);
+        let mut list: LinkedList<i32> = values.iter().copied().collect();
+
+        let mut calls = 0;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            power_sort_list(&mut list, |a, b| {
+                calls += 1;
+                assert!(calls < 3000, "comparison failed");
+                a < b
+            })
+        }));
+        assert!(result.is_err());
+
+        let mut before = values;
+        before.sort();
+        let mut after: Vec<i32> = list.into_iter().collect();
+        after.sort();
+        assert_eq!(after, before);
+    }
+}
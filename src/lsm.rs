@@ -0,0 +1,748 @@
+//! A small embedded key-value store: a log-structured merge tree whose compaction follows the
+//! PowerSort merge rule.
+//!
+//! Writes go to an in-memory table, and are appended to a write-ahead log so they survive a
+//! restart. When the table is full it's flushed to a new sorted segment file, and the log is
+//! cleared. Segments are ordered from the oldest to the newest, like the runs on PowerSort's stack:
+//! after each flush, the two segments below the new one are merged while the power of the boundary
+//! between them is greater than the power of the boundary with the new one, computed by
+//! [node_power] over segment sizes. Like for runs, this keeps the number of segments logarithmic,
+//! and merges segments of similar sizes.
+//!
+//! A segment file holds its entries sorted by key, and deleted keys as tombstones, which are
+//! dropped once they reach the oldest segment. Keys and the positions of values are kept in
+//! memory, values are read from the files when needed.
+//!
+//! Files are replaced by writing a temporary file and renaming it. A merged segment is named after
+//! the newest segment it replaces and records the oldest one, so the files left behind by an
+//! interrupted compaction are recognized and removed when the store is opened again.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+
+use crate::lazy::merge_sorted_iters;
+use crate::powersort_final::node_power;
+
+const SEGMENT_MAGIC: &[u8; 4] = b"PSEG";
+// The magic, the id of the oldest segment merged into this one, and the number of entries.
+const HEADER_LENGTH: u64 = 20;
+const WAL_NAME: &str = "wal.log";
+
+const TOMBSTONE: u8 = 0;
+const VALUE: u8 = 1;
+
+/// A key and its value, or `None` if it was deleted.
+type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+/// Where the value of an entry is stored in its segment file.
+#[derive(Clone, Copy, Debug)]
+struct ValueRef {
+    offset: u64,
+    length: u32,
+}
+
+/// A sorted segment file, with its keys loaded in memory.
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    /// The id of the oldest segment merged into this one.
+    first_id: u64,
+    /// The power of the boundary between this segment and the next one, unused for the newest.
+    power: usize,
+    file: SegmentFile,
+    /// The keys in order, with their values or `None` for tombstones.
+    entries: Vec<(Vec<u8>, Option<ValueRef>)>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id:020}.seg"))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R, length: u32) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; length as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// The file of a segment, read from several threads at once. Where the platform has positional
+/// reads it's a plain file, elsewhere a lock keeps each seek and read together.
+#[cfg(any(unix, windows))]
+type SegmentFile = File;
+#[cfg(not(any(unix, windows)))]
+type SegmentFile = std::sync::Mutex<File>;
+
+/// Fills `buf` with the bytes of `file` at `offset`. The read doesn't depend on the position of
+/// the file's cursor, so a file can be read from several threads at once.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Fills `buf` with the bytes of `file` at `offset`. The read doesn't depend on the position of
+/// the file's cursor, so a file can be read from several threads at once.
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(read) => {
+                buf = &mut buf[read..];
+                offset += read as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Fills `buf` with the bytes of `file` at `offset`, holding the lock from the seek to the end of
+/// the read so reads from other threads can't move the cursor in between.
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(file: &SegmentFile, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    let mut file = file.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+/// Writes an entry, in the same format for the log and for segments: the key and its length, then
+/// a tag telling if there is a value, and the value and its length.
+fn write_entry<W: Write>(writer: &mut W, key: &[u8], value: Option<&[u8]>) -> io::Result<()> {
+    let length = |bytes: &[u8]| {
+        u32::try_from(bytes.len()).map_err(|_| invalid_data("key or value too long"))
+    };
+
+    writer.write_all(&length(key)?.to_le_bytes())?;
+    writer.write_all(key)?;
+    match value {
+        None => writer.write_all(&[TOMBSTONE]),
+        Some(value) => {
+            writer.write_all(&[VALUE])?;
+            writer.write_all(&length(value)?.to_le_bytes())?;
+            writer.write_all(value)
+        }
+    }
+}
+
+/// Reads an entry written by [write_entry], along with its length in bytes.
+fn read_entry<R: Read>(reader: &mut R) -> io::Result<(Entry, u64)> {
+    let key_length = read_u32(reader)?;
+    let key = read_bytes(reader, key_length)?;
+    let value = match read_u8(reader)? {
+        TOMBSTONE => None,
+        VALUE => {
+            let length = read_u32(reader)?;
+            Some(read_bytes(reader, length)?)
+        }
+        _ => return Err(invalid_data("unknown entry tag")),
+    };
+
+    let length = 4 + key.len() + 1 + value.as_ref().map_or(0, |v| 4 + v.len());
+    Ok(((key, value), length as u64))
+}
+
+/// Writes the sorted `entries` to a new segment file and opens it. An existing segment with the
+/// same id is replaced.
+fn write_segment<I>(dir: &Path, id: u64, first_id: u64, entries: I) -> io::Result<Segment>
+where
+    I: IntoIterator<Item = io::Result<Entry>>,
+{
+    let path = segment_path(dir, id);
+    let tmp = path.with_extension("tmp");
+
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    writer.write_all(SEGMENT_MAGIC)?;
+    writer.write_all(&first_id.to_le_bytes())?;
+    writer.write_all(&0u64.to_le_bytes())?;
+
+    let mut count = 0u64;
+    for entry in entries {
+        let (key, value) = entry?;
+        write_entry(&mut writer, &key, value.as_deref())?;
+        count += 1;
+    }
+
+    // The number of entries is only known at the end.
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(HEADER_LENGTH - 8))?;
+    file.write_all(&count.to_le_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, &path)?;
+    Segment::open(&path, id)
+}
+
+impl Segment {
+    /// Opens the segment file at `path` and loads its keys.
+    fn open(path: &Path, id: u64) -> io::Result<Segment> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(&file);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != SEGMENT_MAGIC {
+            return Err(invalid_data("not a segment file"));
+        }
+        let first_id = read_u64(&mut reader)?;
+        let count = read_u64(&mut reader)?;
+
+        let mut entries = Vec::new();
+        let mut offset = HEADER_LENGTH;
+        for _ in 0..count {
+            let key_length = read_u32(&mut reader)?;
+            let key = read_bytes(&mut reader, key_length)?;
+            offset += 4 + key.len() as u64 + 1;
+
+            let value = match read_u8(&mut reader)? {
+                TOMBSTONE => None,
+                VALUE => {
+                    let length = read_u32(&mut reader)?;
+                    offset += 4;
+                    // Values are not loaded, only their position.
+                    reader.seek_relative(length.into())?;
+                    let value = ValueRef { offset, length };
+                    offset += u64::from(length);
+                    Some(value)
+                }
+                _ => return Err(invalid_data("unknown entry tag")),
+            };
+            entries.push((key, value));
+        }
+
+        drop(reader);
+        Ok(Segment { id, first_id, power: 0, file: SegmentFile::from(file), entries })
+    }
+
+    /// The size of the segment for the power rule. Empty segments count as one entry, since powers
+    /// are only defined for non-empty runs.
+    fn weight(&self) -> usize {
+        self.entries.len().max(1)
+    }
+
+    fn read_value(&self, value: ValueRef) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; value.length as usize];
+        read_exact_at(&self.file, &mut buf, value.offset)?;
+        Ok(buf)
+    }
+
+    /// Returns the version of `key` in this segment, `Some(None)` being a tombstone.
+    fn get(&self, key: &[u8]) -> Option<Option<ValueRef>> {
+        let i = self.entries.binary_search_by(|entry| entry.0.as_slice().cmp(key)).ok()?;
+        Some(self.entries[i].1)
+    }
+
+    /// Returns the entries with keys within `bounds`.
+    fn range(&self, bounds: (Bound<&[u8]>, Bound<&[u8]>)) -> &[(Vec<u8>, Option<ValueRef>)] {
+        let entries = &self.entries;
+        let start = match bounds.0 {
+            Bound::Included(x) => entries.partition_point(|e| e.0.as_slice() < x),
+            Bound::Excluded(x) => entries.partition_point(|e| e.0.as_slice() <= x),
+            Bound::Unbounded => 0,
+        };
+        let end = match bounds.1 {
+            Bound::Included(x) => entries.partition_point(|e| e.0.as_slice() <= x),
+            Bound::Excluded(x) => entries.partition_point(|e| e.0.as_slice() < x),
+            Bound::Unbounded => entries.len(),
+        };
+        &entries[start..end.max(start)]
+    }
+}
+
+/// A version of a key found by a scan.
+#[derive(Clone, Copy, Debug)]
+enum Version<'a> {
+    Memory(Option<&'a [u8]>),
+    Disk(&'a Segment, Option<ValueRef>),
+}
+
+/// A key-value store kept in a directory. See the [module documentation](self) for how it works.
+///
+/// Keys and values are byte strings. Keys are ordered lexicographically.
+#[derive(Debug)]
+pub struct Store {
+    dir: PathBuf,
+    /// The writes since the last flush, with `None` for deleted keys.
+    memtable: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    memtable_limit: usize,
+    wal: File,
+    /// From the oldest to the newest.
+    segments: Vec<Segment>,
+    /// A power of two at least the total weight of the segments, the length powers are computed
+    /// for.
+    capacity: usize,
+    next_id: u64,
+}
+
+impl Store {
+    /// Opens the store in `dir`, creating it if needed, and recovers its state: segments left over
+    /// by an interrupted compaction are removed, and writes that were not flushed are replayed
+    /// from the log.
+    ///
+    /// The in-memory table is flushed to a segment once it holds `memtable_limit` keys.
+    pub fn open<P: AsRef<Path>>(dir: P, memtable_limit: usize) -> io::Result<Store> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                // A segment that was never completed.
+                Some("tmp") => fs::remove_file(&path)?,
+                Some("seg") => {
+                    let id = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok());
+                    let id = id.ok_or_else(|| invalid_data("invalid segment file name"))?;
+                    segments.push(Segment::open(&path, id)?);
+                }
+                _ => {}
+            }
+        }
+        segments.sort_by_key(|s| s.id);
+
+        // Segments replaced by a newer merged segment are still there if its compaction was
+        // interrupted.
+        let mut covered_from = u64::MAX;
+        let mut kept = Vec::with_capacity(segments.len());
+        for segment in segments.into_iter().rev() {
+            if segment.id >= covered_from {
+                let path = segment_path(&dir, segment.id);
+                drop(segment);
+                fs::remove_file(path)?;
+            } else {
+                covered_from = segment.first_id;
+                kept.push(segment);
+            }
+        }
+        kept.reverse();
+
+        let next_id = kept.last().map_or(0, |s| s.id + 1);
+        let wal =
+            OpenOptions::new().read(true).append(true).create(true).open(dir.join(WAL_NAME))?;
+
+        let mut store = Store {
+            dir,
+            memtable: BTreeMap::new(),
+            memtable_limit,
+            wal,
+            segments: kept,
+            capacity: 1,
+            next_id,
+        };
+        store.compute_powers();
+        store.replay()?;
+        Ok(store)
+    }
+
+    /// Loads the writes in the log into the in-memory table. A write cut short by a crash ends the
+    /// log, and is discarded.
+    fn replay(&mut self) -> io::Result<()> {
+        let mut reader = BufReader::new(&self.wal);
+        let mut length = 0;
+
+        loop {
+            match read_entry(&mut reader) {
+                Ok(((key, value), entry_length)) => {
+                    self.memtable.insert(key, value);
+                    length += entry_length;
+                }
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.wal.set_len(length)
+    }
+
+    /// Returns the value of `key`, or `None` if it's not in the store.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(value.clone());
+        }
+
+        // The newest version wins.
+        for segment in self.segments.iter().rev() {
+            if let Some(value) = segment.get(key) {
+                return value.map(|v| segment.read_value(v)).transpose();
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the keys within `range` and their values, in key order.
+    ///
+    /// The matching entries of the in-memory table and of every segment are merged lazily with
+    /// [merge_sorted_iters], newest first, so the first version of each key is the one to keep.
+    pub fn scan<K, R>(&self, range: R) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let bounds = (range.start_bound().map(K::as_ref), range.end_bound().map(K::as_ref));
+
+        type Source<'a> = std::vec::IntoIter<(&'a [u8], Version<'a>)>;
+        let mut sources: Vec<(Source, Option<usize>)> = Vec::with_capacity(self.segments.len() + 1);
+
+        let memory: Vec<_> = self
+            .memtable
+            .range::<[u8], _>(bounds)
+            .map(|(k, v)| (k.as_slice(), Version::Memory(v.as_deref())))
+            .collect();
+        sources.push((memory.into_iter(), None));
+        for segment in self.segments.iter().rev() {
+            let disk: Vec<_> = segment
+                .range(bounds)
+                .iter()
+                .map(|(k, v)| (k.as_slice(), Version::Disk(segment, *v)))
+                .collect();
+            sources.push((disk.into_iter(), None));
+        }
+        for (source, size) in &mut sources {
+            *size = Some(source.len());
+        }
+
+        let mut result = Vec::new();
+        let mut last = None;
+        for (key, version) in merge_sorted_iters(sources, |a, b| a.0 < b.0) {
+            if last == Some(key) {
+                continue;
+            }
+            last = Some(key);
+
+            let value = match version {
+                Version::Memory(value) => value.map(<[u8]>::to_vec),
+                Version::Disk(segment, value) => {
+                    value.map(|v| segment.read_value(v)).transpose()?
+                }
+            };
+            if let Some(value) = value {
+                result.push((key.to_vec(), value));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Sets the value of `key`.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.write(key, Some(value))
+    }
+
+    /// Removes `key` from the store.
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<()> {
+        self.write(key, None)
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<&[u8]>) -> io::Result<()> {
+        // A single write, so a crash can only cut the last entry of the log.
+        let mut record = Vec::new();
+        write_entry(&mut record, key, value)?;
+        self.wal.write_all(&record)?;
+
+        self.memtable.insert(key.to_vec(), value.map(<[u8]>::to_vec));
+        if self.memtable.len() >= self.memtable_limit {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the in-memory table to a new segment, clears the log and merges segments following
+    /// the power rule.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+
+        // Tombstones only hide older versions, there are none below the oldest segment.
+        let keep_tombstones = !self.segments.is_empty();
+        let entries = self
+            .memtable
+            .iter()
+            .filter(|(_, v)| keep_tombstones || v.is_some())
+            .map(|(k, v)| Ok((k.clone(), v.clone())));
+        let segment = write_segment(&self.dir, self.next_id, self.next_id, entries)?;
+        self.next_id += 1;
+
+        self.wal.set_len(0)?;
+        self.wal.sync_all()?;
+        self.memtable.clear();
+
+        self.push_segment(segment)
+    }
+
+    /// Merges all segments into one, after flushing the in-memory table.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.flush()?;
+        while self.segments.len() > 1 {
+            self.merge_segments(self.segments.len() - 2)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of entries of each segment, from the oldest to the newest, counting
+    /// tombstones.
+    pub fn segment_sizes(&self) -> Vec<usize> {
+        self.segments.iter().map(|s| s.entries.len()).collect()
+    }
+
+    /// Computes the powers of the boundaries between the segments of a store just opened.
+    fn compute_powers(&mut self) {
+        let total: usize = self.segments.iter().map(Segment::weight).sum();
+        self.capacity = total.next_power_of_two();
+
+        let mut start = 0;
+        for i in 1..self.segments.len() {
+            let (n1, n2) = (self.segments[i - 1].weight(), self.segments[i].weight());
+            self.segments[i - 1].power = node_power(start, n1, n2, self.capacity);
+            start += n1;
+        }
+    }
+
+    /// Pushes a new segment, after merging the segments below it while the power rule says so,
+    /// like PowerSort merges the runs on its stack before pushing a new one.
+    ///
+    /// Like in a [SortedVec](crate::sorted_vec::SortedVec), every segment keeps the power of its
+    /// boundary with the next one, computed when that one was pushed, so the powers compared are
+    /// all computed from the positions they had at the time.
+    fn push_segment(&mut self, segment: Segment) -> io::Result<()> {
+        let start: usize = self.segments.iter().map(Segment::weight).sum();
+
+        // The store keeps growing, so powers are computed for a power of two instead of the total
+        // weight. Doubling it adds one to the power of every boundary, so they stay comparable.
+        while self.capacity < start + segment.weight() {
+            self.capacity *= 2;
+            self.segments.iter_mut().for_each(|s| s.power += 1);
+        }
+
+        if let Some(last) = self.segments.last() {
+            // Compute power between segments.
+            let (n1, n2) = (last.weight(), segment.weight());
+            let power = node_power(start - n1, n1, n2, self.capacity);
+
+            // Merge if possible.
+            while self.segments.len() > 1 && self.segments[self.segments.len() - 2].power > power {
+                self.merge_segments(self.segments.len() - 2)?;
+            }
+
+            self.segments.last_mut().unwrap().power = power;
+        }
+
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    /// Merges segments `i` and `i + 1` into a single segment.
+    fn merge_segments(&mut self, i: usize) -> io::Result<()> {
+        let (left, right) = (&self.segments[i], &self.segments[i + 1]);
+        let drop_tombstones = i == 0;
+
+        let (mut a, mut b) = (0, 0);
+        let entries = std::iter::from_fn(|| loop {
+            let take_left = match (left.entries.get(a), right.entries.get(b)) {
+                (None, None) => return None,
+                (Some(x), Some(y)) => {
+                    // If equal, the newer version wins.
+                    if x.0 == y.0 {
+                        a += 1;
+                    }
+                    x.0 < y.0
+                }
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+            };
+
+            let (segment, (key, value)) = if take_left {
+                a += 1;
+                (left, &left.entries[a - 1])
+            } else {
+                b += 1;
+                (right, &right.entries[b - 1])
+            };
+            if drop_tombstones && value.is_none() {
+                continue;
+            }
+            return Some(
+                value.map(|v| segment.read_value(v)).transpose().map(|v| (key.clone(), v)),
+            );
+        });
+
+        // The merged segment replaces the newer one, and then the older one is removed.
+        let mut merged = write_segment(&self.dir, right.id, left.first_id, entries)?;
+        merged.power = right.power;
+        let left_path = segment_path(&self.dir, left.id);
+        self.segments.splice(i..=i + 1, [merged]);
+        fs::remove_file(left_path)
+    }
+}
+
+#[cfg(test)]
+mod lsm_tests {
+    use std::collections::BTreeMap;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::{segment_path, write_segment, Store, WAL_NAME};
+    use crate::sequences::generate_random_sequence_in_range;
+
+    // An empty directory for a test, in the system's temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("powersort-lsm-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn key(x: i32) -> Vec<u8> {
+        format!("key{x:05}").into_bytes()
+    }
+
+    // Applies random puts and deletes to the store and to a map.
+    fn random_writes(store: &mut Store, model: &mut BTreeMap<Vec<u8>, Vec<u8>>, count: usize) {
+        let keys = generate_random_sequence_in_range(count, 0, 500);
+        for (i, k) in keys.into_iter().enumerate() {
+            if k % 5 == 0 {
+                store.delete(&key(k)).unwrap();
+                model.remove(&key(k));
+            } else {
+                let value = format!("value{i}").into_bytes();
+                store.put(&key(k), &value).unwrap();
+                model.insert(key(k), value);
+            }
+        }
+    }
+
+    fn check(store: &Store, model: &BTreeMap<Vec<u8>, Vec<u8>>) {
+        for k in 0..500 {
+            assert_eq!(store.get(&key(k)).unwrap().as_ref(), model.get(&key(k)));
+        }
+
+        let all: Vec<_> = model.clone().into_iter().collect();
+        assert_eq!(store.scan::<&[u8], _>(..).unwrap(), all);
+
+        let (start, end) = (key(120), key(300));
+        let expected: Vec<_> =
+            model.range(start.clone()..=end.clone()).map(|(k, v)| (k.clone(), v.clone())).collect();
+        assert_eq!(store.scan(start..=end).unwrap(), expected);
+    }
+
+    #[test]
+    fn lsm_test_1() {
+        let dir = temp_dir("writes");
+        let mut store = Store::open(&dir, 16).unwrap();
+        let mut model = BTreeMap::new();
+
+        for _ in 0..5 {
+            random_writes(&mut store, &mut model, 400);
+            check(&store, &model);
+        }
+
+        // The power rule keeps the number of segments logarithmic.
+        assert!(store.segment_sizes().len() <= 16);
+
+        store.compact().unwrap();
+        assert_eq!(store.segment_sizes(), vec![model.len()]);
+        check(&store, &model);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    // Reopening the store recovers the segments and the writes that were not flushed.
+    fn lsm_test_2() {
+        let dir = temp_dir("restart");
+        let mut model = BTreeMap::new();
+
+        {
+            let mut store = Store::open(&dir, 50).unwrap();
+            random_writes(&mut store, &mut model, 1000);
+            store.put(b"unflushed", b"yes").unwrap();
+            model.insert(b"unflushed".to_vec(), b"yes".to_vec());
+        }
+
+        // A write cut short by a crash.
+        let mut wal = OpenOptions::new().append(true).open(dir.join(WAL_NAME)).unwrap();
+        wal.write_all(&[9, 0, 0, 0, b'k']).unwrap();
+        drop(wal);
+
+        let mut store = Store::open(&dir, 50).unwrap();
+        check(&store, &model);
+
+        random_writes(&mut store, &mut model, 300);
+        drop(store);
+        let store = Store::open(&dir, 50).unwrap();
+        check(&store, &model);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    // The segments replaced by a compaction that didn't finish are removed on restart.
+    fn lsm_test_3() {
+        let dir = temp_dir("compaction");
+        let mut store = Store::open(&dir, 1000).unwrap();
+        store.put(b"a", b"1").unwrap();
+        store.flush().unwrap();
+        store.put(b"b", b"2").unwrap();
+        store.delete(b"a").unwrap();
+        store.compact().unwrap();
+        assert_eq!(store.segment_sizes(), vec![1]);
+        drop(store);
+
+        // Put back the oldest segment, as if the compaction stopped before removing it.
+        write_segment(&dir, 0, 0, [Ok((b"a".to_vec(), Some(b"1".to_vec())))]).unwrap();
+        assert!(segment_path(&dir, 0).exists());
+
+        let store = Store::open(&dir, 1000).unwrap();
+        assert!(!segment_path(&dir, 0).exists());
+        assert_eq!(store.get(b"a").unwrap(), None);
+        assert_eq!(store.scan(b"a".as_slice()..).unwrap(), vec![(b"b".to_vec(), b"2".to_vec())]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    // Reads from several threads at once see the right values.
+    fn lsm_test_4() {
+        let dir = temp_dir("threads");
+        let mut store = Store::open(&dir, 16).unwrap();
+        let mut model = BTreeMap::new();
+        random_writes(&mut store, &mut model, 2000);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| (0..5).for_each(|_| check(&store, &model)));
+            }
+        });
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+//! Safe merging of two sorted sequences into a new one.
+
+use std::mem::size_of;
+use std::{ptr, slice};
+
+// After this many elements in a row are taken from the same run, the rest of the block is found
+// by galloping instead of one comparison at a time.
+const MIN_GALLOP: usize = 7;
+
+/// Returns the first index of `v` for which `pred` is false, `pred` being true for a prefix of `v`
+/// and false for the rest.
+///
+/// The bound is searched exponentially from the start of `v`, so this takes *O*(log(*k*))
+/// comparisons for an answer of `k`, instead of *O*(log(*n*)) for a plain binary search.
+pub(crate) fn gallop_from_start<T, P>(v: &[T], mut pred: P) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    let mut bound = 1;
+    while bound <= v.len() && pred(&v[bound - 1]) {
+        bound *= 2;
+    }
+
+    // `pred` is true for everything before `bound / 2`, and false at `bound - 1` if it exists.
+    let (lo, hi) = (bound / 2, bound.min(v.len()));
+    lo + v[lo..hi].partition_point(pred)
+}
+
+/// Like [gallop_from_start], but searches from the end of `v`, in *O*(log(*n* - *k*)) comparisons.
+pub(crate) fn gallop_from_end<T, P>(v: &[T], mut pred: P) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    let n = v.len();
+    let mut bound = 1;
+    while bound <= n && !pred(&v[n - bound]) {
+        bound *= 2;
+    }
+
+    // `pred` is false for everything from `n - bound / 2`, and true at `n - bound` if it exists.
+    let (lo, hi) = (n.saturating_sub(bound), n - bound / 2);
+    lo + v[lo..hi].partition_point(pred)
+}
+
+/// Merges the sorted slices `a` and `b` into `out`, appending the merged elements after those
+/// already in it.
+///
+/// If one slice goes entirely before the other, they are copied as they are after two comparisons.
+/// Otherwise they are merged, galloping over long blocks of elements that come from the same
+/// slice, so merging a short slice into a long one takes few comparisons.
+///
+/// The merge is stable: equal elements are taken from `a` before `b`.
+pub fn merge_into<T, F>(a: &[T], b: &[T], out: &mut Vec<T>, mut is_less: F)
+where
+    T: Clone,
+    F: FnMut(&T, &T) -> bool,
+{
+    out.reserve(a.len() + b.len());
+
+    let (na, nb) = (a.len(), b.len());
+    if na == 0 || nb == 0 || !is_less(&b[0], &a[na - 1]) {
+        out.extend_from_slice(a);
+        out.extend_from_slice(b);
+        return;
+    }
+    if is_less(&b[nb - 1], &a[0]) {
+        out.extend_from_slice(b);
+        out.extend_from_slice(a);
+        return;
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let (mut wins_a, mut wins_b) = (0, 0);
+
+    while i < na && j < nb {
+        if is_less(&b[j], &a[i]) {
+            let take = if wins_b >= MIN_GALLOP { gallop_from_start(&b[j..], |x| is_less(x, &a[i])) } else { 1 };
+            out.extend_from_slice(&b[j..j + take]);
+            j += take;
+            wins_b += 1;
+            wins_a = 0;
+        } else {
+            // If equal, prefer `a` to maintain stability.
+            let take = if wins_a >= MIN_GALLOP { gallop_from_start(&a[i..], |x| !is_less(&b[j], x)) } else { 1 };
+            out.extend_from_slice(&a[i..i + take]);
+            i += take;
+            wins_a += 1;
+            wins_b = 0;
+        }
+    }
+
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+}
+
+/// Merges the sorted vectors `a` and `b` into one, moving their elements.
+///
+/// The allocation of whichever input has the larger capacity is reused, and only grown if it
+/// can't hold both. Like [merge_into], non-overlapping inputs are simply concatenated, and long
+/// blocks from the same input are found by galloping.
+///
+/// The merge is stable: equal elements are taken from `a` before `b`.
+pub fn merge_vecs<T, F>(mut a: Vec<T>, mut b: Vec<T>, mut is_less: F) -> Vec<T>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (na, nb) = (a.len(), b.len());
+    if na == 0 {
+        return b;
+    }
+    if nb == 0 || size_of::<T>() == 0 {
+        a.append(&mut b);
+        return a;
+    }
+
+    let ordered = !is_less(&b[0], &a[na - 1]);
+    let reversed = !ordered && is_less(&b[nb - 1], &a[0]);
+
+    // The larger input is merged into in place, the elements of the other one are moved into it.
+    let left_in_place = a.capacity() >= b.capacity();
+    let (mut dst, mut ext) = if left_in_place { (a, b) } else { (b, a) };
+    let ext_len = ext.len();
+    dst.reserve(ext_len);
+
+    unsafe {
+        // The elements of `ext` are now owned by `dst`, only its allocation is dropped.
+        ext.set_len(0);
+        let (d, e) = (dst.as_mut_ptr(), ext.as_ptr());
+
+        if ordered || reversed {
+            // Whether the elements of `dst` go after those of `ext`.
+            let dst_after = ordered != left_in_place;
+            if dst_after {
+                ptr::copy(d, d.add(ext_len), dst.len());
+                ptr::copy_nonoverlapping(e, d, ext_len);
+            } else {
+                ptr::copy_nonoverlapping(e, d.add(dst.len()), ext_len);
+            }
+            dst.set_len(na + nb);
+        } else {
+            merge_back(&mut dst, e, ext_len, left_in_place, &mut is_less);
+        }
+    }
+
+    dst
+}
+
+/// Merges the run in `dst` with the `ext_len` elements at `ext` from the back, leaving the result
+/// in `dst`. The run in `dst` is the left one if `left_in_place`, the right one otherwise.
+///
+/// # Safety
+///
+/// `dst` must have capacity for both runs, and `ext` must be valid for reads of `ext_len`
+/// elements, which are moved into `dst`. Both runs must be non-empty and `T` must not be a
+/// zero-sized type.
+unsafe fn merge_back<T, F>(dst: &mut Vec<T>, ext: *const T, ext_len: usize, left_in_place: bool, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let d = dst.as_mut_ptr();
+    let (left, right) = if left_in_place { (d as *const T, ext) } else { (ext, d as *const T) };
+    let (i, j) = if left_in_place { (dst.len(), ext_len) } else { (ext_len, dst.len()) };
+
+    // The merged elements are written from the back, so the rest of the run in `dst` never moves
+    // until it's its turn. If `is_less` panics, `gap` moves what's left of `ext` into `dst`.
+    let mut gap = Gap { dst, len: i + j, ext, left_in_place, i, j };
+    let (mut wins_left, mut wins_right) = (0, 0);
+
+    while gap.i > 0 && gap.j > 0 {
+        let (i, j) = (gap.i, gap.j);
+        let take_left;
+        let take;
+
+        unsafe {
+            let (l, r) = (slice::from_raw_parts(left, i), slice::from_raw_parts(right, j));
+            if is_less(&r[j - 1], &l[i - 1]) {
+                // The left element is greater, it goes last.
+                take_left = true;
+                take = if wins_left >= MIN_GALLOP { i - gallop_from_end(l, |x| !is_less(&r[j - 1], x)) } else { 1 };
+                wins_left += 1;
+                wins_right = 0;
+            } else {
+                // If equal, prefer the right run to go last to maintain stability.
+                take_left = false;
+                take = if wins_right >= MIN_GALLOP { j - gallop_from_end(r, |x| is_less(x, &l[i - 1])) } else { 1 };
+                wins_right += 1;
+                wins_left = 0;
+            }
+        }
+
+        unsafe {
+            if take_left {
+                ptr::copy(left.add(i - take), d.add(i + j - take), take);
+                gap.i -= take;
+            } else {
+                ptr::copy(right.add(j - take), d.add(i + j - take), take);
+                gap.j -= take;
+            }
+        }
+    }
+    // Finally, `gap` gets dropped. The rest of the run in `dst` is already in place, and the rest
+    // of `ext` gets moved to the front of `dst`.
+
+    /// The state of a backwards merge: `i` and `j` elements are left in the left and right runs,
+    /// which add up to `len` elements.
+    struct Gap<'a, T> {
+        dst: &'a mut Vec<T>,
+        len: usize,
+        ext: *const T,
+        left_in_place: bool,
+        i: usize,
+        j: usize,
+    }
+
+    impl<T> Drop for Gap<'_, T> {
+        fn drop(&mut self) {
+            let (in_place, rest) = if self.left_in_place { (self.i, self.j) } else { (self.j, self.i) };
+            unsafe {
+                // The gap in `dst` is right after what's left of its own run, and as long as the
+                // rest of `ext`.
+                ptr::copy_nonoverlapping(self.ext, self.dst.as_mut_ptr().add(in_place), rest);
+                self.dst.set_len(self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod merge_tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    use super::{gallop_from_end, gallop_from_start, merge_into, merge_vecs};
+    use crate::sequences::generate_random_sequence_in_range;
+
+    type Tagged = Vec<(i32, usize)>;
+
+    // Two sorted vectors of tagged keys, to check stability.
+    fn sorted_pair(na: usize, nb: usize, range: i32) -> (Tagged, Tagged) {
+        let mut a: Vec<(i32, usize)> = generate_random_sequence_in_range(na, 0, range).into_iter().zip(0..).collect();
+        let mut b: Vec<(i32, usize)> = generate_random_sequence_in_range(nb, 0, range).into_iter().zip(na..).collect();
+        a.sort_by_key(|x| x.0);
+        b.sort_by_key(|x| x.0);
+        (a, b)
+    }
+
+    fn expected(a: &[(i32, usize)], b: &[(i32, usize)]) -> Vec<(i32, usize)> {
+        let mut v: Vec<(i32, usize)> = a.iter().chain(b.iter()).copied().collect();
+        v.sort_by_key(|x| x.0);
+        v
+    }
+
+    #[test]
+    fn gallop_test_1() {
+        let v: Vec<i32> = (0..100).collect();
+        for k in 0..=100 {
+            assert_eq!(gallop_from_start(&v, |&x| x < k), k as usize);
+            assert_eq!(gallop_from_end(&v, |&x| x < k), k as usize);
+        }
+    }
+
+    #[test]
+    fn merge_into_test_1() {
+        for (na, nb, range) in [(500, 500, 50), (1000, 10, 1000), (3, 800, 20), (0, 10, 5)] {
+            let (a, b) = sorted_pair(na, nb, range);
+            let mut out = vec![(-1, 0)];
+            merge_into(&a, &b, &mut out, |x, y| x.0 < y.0);
+            assert_eq!(out[0], (-1, 0));
+            assert_eq!(out[1..], expected(&a, &b));
+        }
+    }
+
+    #[test]
+    // Non-overlapping inputs in either order.
+    fn merge_into_test_2() {
+        let (a, b): (Vec<i32>, Vec<i32>) = ((0..10).collect(), (10..20).collect());
+        let mut out = Vec::new();
+        merge_into(&b, &a, &mut out, |x, y| x < y);
+        assert!(out.into_iter().eq(0..20));
+    }
+
+    #[test]
+    fn merge_vecs_test_1() {
+        for (na, nb, range) in [(500, 500, 50), (1000, 10, 1000), (3, 800, 20), (0, 10, 5), (10, 0, 5)] {
+            let (a, b) = sorted_pair(na, nb, range);
+            let merged = merge_vecs(a.clone(), b.clone(), |x, y| x.0 < y.0);
+            assert_eq!(merged, expected(&a, &b));
+        }
+    }
+
+    #[test]
+    // The allocation of the input with the larger capacity is reused.
+    fn merge_vecs_test_2() {
+        for (first, second) in [((0..100).step_by(2), (1..40).step_by(2)), ((50..100).step_by(1), (0..50).step_by(1))] {
+            let mut large: Vec<String> = Vec::with_capacity(200);
+            large.extend(first.map(|x| format!("{:03}", x)));
+            let small: Vec<String> = second.map(|x| format!("{:03}", x)).collect();
+
+            let mut sorted: Vec<String> = large.iter().chain(small.iter()).cloned().collect();
+            sorted.sort();
+
+            let ptr = large.as_ptr();
+            let merged = merge_vecs(small, large, |x, y| x < y);
+            assert_eq!(merged.as_ptr(), ptr);
+            assert_eq!(merged, sorted);
+        }
+    }
+
+    #[test]
+    // If the comparison panics, every element is dropped exactly once.
+    fn merge_vecs_test_3() {
+        let values: Vec<Rc<i32>> = (0..200).map(Rc::new).collect();
+        let a: Vec<Rc<i32>> = values.iter().step_by(2).cloned().collect();
+        let b: Vec<Rc<i32>> = values.iter().skip(1).step_by(2).cloned().collect();
+
+        let mut comparisons = 0;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            merge_vecs(a, b, |x, y| {
+                comparisons += 1;
+                if comparisons == 50 {
+                    panic!("comparison failed");
+                }
+                x < y
+            })
+        }));
+
+        assert!(result.is_err());
+        assert!(values.iter().all(|x| Rc::strong_count(x) == 1));
+    }
+}
@@ -0,0 +1,179 @@
+//! Stable partitioning of a slice by a predicate.
+
+use std::mem::size_of;
+use std::ptr;
+
+/// Moves the elements of `v` for which `pred` is true before those for which it's false, keeping
+/// the relative order within both groups. Returns the number of elements for which it's true.
+///
+/// Takes *O*(*n*) time, using a scratch buffer of up to `v.len()` elements. See
+/// [stable_partition_in_place] for a version without a buffer.
+/// # Arguments
+/// - `v`: The sequence to partition.
+/// - `pred`: Called exactly once per element, in order.
+/// # Returns
+/// The index of the first element for which `pred` was false, or `v.len()` if there is none.
+pub fn stable_partition<T, P>(v: &mut [T], mut pred: P) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    if size_of::<T>() == 0 {
+        return v.iter().filter(|x| pred(x)).count();
+    }
+
+    // Buffer for the elements for which `pred` is false.
+    let mut buf = Vec::with_capacity(v.len());
+
+    unsafe { partition_buffer(v, &mut pred, buf.as_mut_ptr()) }
+}
+
+/// Partitions `v` like [stable_partition], using `buf` as temporary storage.
+///
+/// Elements for which `pred` is true are shifted towards the front of `v`, and the others are
+/// copied into `buf`, from where they are copied back after the last one.
+///
+/// # Safety
+///
+/// Buffer `buf` must be long enough to hold `v.len()` elements, and `T` must not be a zero-sized
+/// type.
+pub unsafe fn partition_buffer<T, P>(v: &mut [T], pred: &mut P, buf: *mut T) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    let len = v.len();
+    let v = v.as_mut_ptr();
+
+    // The elements in `buf` always go right after the elements kept in `v`, so they fill the hole
+    // between those and the next element to look at. If `pred` panics, `hole` copies them back.
+    let mut hole = PartitionHole { start: buf, end: buf, dest: v };
+
+    for i in 0..len {
+        unsafe {
+            let x = v.add(i);
+            if pred(&*x) {
+                ptr::copy(x, hole.dest, 1);
+                hole.dest = hole.dest.add(1);
+            } else {
+                ptr::copy_nonoverlapping(x, hole.end, 1);
+                hole.end = hole.end.add(1);
+            }
+        }
+    }
+
+    // Finally, `hole` gets dropped and copies the elements in `buf` after the kept ones.
+    unsafe { hole.dest.offset_from(v) as usize }
+}
+
+// When dropped, copies the range `start..end` into `dest..`.
+struct PartitionHole<T> {
+    start: *mut T,
+    end: *mut T,
+    dest: *mut T,
+}
+
+impl<T> Drop for PartitionHole<T> {
+    fn drop(&mut self) {
+        // `T` is not a zero-sized type, and these are pointers into a buffer's elements.
+        unsafe {
+            let len = self.end.offset_from(self.start) as usize;
+            ptr::copy_nonoverlapping(self.start, self.dest, len);
+        }
+    }
+}
+
+/// Like [stable_partition], but without any scratch memory.
+///
+/// Both halves of `v` are partitioned recursively, and then the false part of the left half is
+/// swapped with the true part of the right one by a rotation. This takes *O*(*n* \* log(*n*))
+/// time.
+pub fn stable_partition_in_place<T, P>(v: &mut [T], mut pred: P) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    partition_rotate(v, &mut pred)
+}
+
+fn partition_rotate<T, P>(v: &mut [T], pred: &mut P) -> usize
+where
+    P: FnMut(&T) -> bool,
+{
+    let len = v.len();
+    if len <= 1 {
+        return v.iter().filter(|x| pred(x)).count();
+    }
+
+    let mid = len / 2;
+    let left = partition_rotate(&mut v[..mid], pred);
+    let right = partition_rotate(&mut v[mid..], pred);
+
+    v[left..mid + right].rotate_left(mid - left);
+    left + right
+}
+
+#[cfg(test)]
+mod stable_partition_tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    use super::{stable_partition, stable_partition_in_place};
+    use crate::sequences::generate_random_sequence_in_range;
+
+    // The expected partition, built with two filters.
+    fn expected<T: Clone>(v: &[T], pred: impl Fn(&T) -> bool) -> Vec<T> {
+        v.iter().filter(|x| pred(x)).chain(v.iter().filter(|x| !pred(x))).cloned().collect()
+    }
+
+    #[test]
+    fn stable_partition_test_1() {
+        let values: Vec<(i32, usize)> = generate_random_sequence_in_range(1000, 0, 100).into_iter().zip(0..).collect();
+        let pred = |x: &(i32, usize)| x.0 % 3 == 0;
+        let count = values.iter().filter(|x| pred(x)).count();
+
+        let mut v = values.clone();
+        assert_eq!(stable_partition(&mut v, pred), count);
+        assert_eq!(v, expected(&values, pred));
+
+        let mut v = values.clone();
+        assert_eq!(stable_partition_in_place(&mut v, pred), count);
+        assert_eq!(v, expected(&values, pred));
+    }
+
+    #[test]
+    fn stable_partition_test_2() {
+        let values: Vec<String> = (0..300).map(|x| x.to_string()).collect();
+        let pred = |x: &String| x.ends_with('7');
+
+        for partition in [stable_partition::<String, fn(&String) -> bool>, stable_partition_in_place] {
+            let mut v = values.clone();
+            let count = partition(&mut v, pred);
+            assert_eq!(count, 30);
+            assert_eq!(v, expected(&values, pred));
+
+            let mut empty: Vec<String> = Vec::new();
+            assert_eq!(partition(&mut empty, pred), 0);
+        }
+    }
+
+    #[test]
+    // If the predicate panics, every element is still in the slice exactly once.
+    fn stable_partition_test_3() {
+        let values: Vec<Rc<i32>> = (0..100).map(Rc::new).collect();
+        let mut v = values.clone();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            stable_partition(&mut v, |x| {
+                if **x == 60 {
+                    panic!("predicate failed");
+                }
+                **x % 2 == 0
+            })
+        }));
+
+        assert!(result.is_err());
+        let mut seen: Vec<i32> = v.iter().map(|x| **x).collect();
+        seen.sort();
+        assert!(seen.into_iter().eq(0..100));
+        drop(v);
+        assert!(values.iter().all(|x| Rc::strong_count(x) == 1));
+    }
+}
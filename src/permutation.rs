@@ -0,0 +1,192 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+
+use crate::target::{power_sort_target, SortTarget};
+
+/// Rearranges `v` in place so that `v[k]` becomes the element previously at `perm[k]`.
+///
+/// The permutation is applied by following its cycles with swaps, so it takes *O*(*n*) time and no
+/// extra memory. `perm` is used to mark visited positions and is left as the identity.
+/// # Arguments
+/// - `v`: The sequence to rearrange.
+/// - `perm`: A permutation of `0..v.len()`.
+/// # Panics
+/// Panics if `perm` has a different length than `v`.
+pub fn apply_permutation<T>(v: &mut [T], perm: &mut [usize]) {
+    assert_eq!(v.len(), perm.len());
+    permute_by_swaps(perm, |i, j| v.swap(i, j));
+}
+
+/// Follows the cycles of `perm`, calling `swap` for every swap needed to move the element at
+/// `perm[k]` to `k`. `perm` is left as the identity.
+fn permute_by_swaps<S: FnMut(usize, usize)>(perm: &mut [usize], mut swap: S) {
+    for i in 0..perm.len() {
+        // Walk the cycle starting at `i`, pulling each wanted element into place.
+        let mut current = i;
+        while perm[current] != i {
+            let next = perm[current];
+            swap(current, next);
+            perm[current] = current;
+            current = next;
+        }
+        perm[current] = current;
+    }
+}
+
+/// A column whose elements can be reordered by [power_sort_columns].
+pub trait Permutable {
+    /// Returns the number of elements.
+    fn len(&self) -> usize;
+
+    /// Returns true if there are no elements.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Swaps the elements at positions `i` and `j`.
+    fn swap(&mut self, i: usize, j: usize);
+}
+
+impl<T> Permutable for [T] {
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        <[T]>::swap(self, i, j)
+    }
+}
+
+impl<T> Permutable for Vec<T> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.as_mut_slice().swap(i, j)
+    }
+}
+
+/// The key column and its companion columns, sorted together as one [SortTarget].
+struct Columns<'a, 'c, K, F> {
+    keys: &'a mut [K],
+    columns: &'a mut [&'c mut dyn Permutable],
+    // Comparisons only borrow the target.
+    is_less: RefCell<F>,
+}
+
+impl<K, F> SortTarget for Columns<'_, '_, K, F>
+where
+    F: FnMut(&K, &K) -> bool,
+{
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn compare(&self, i: usize, j: usize) -> Ordering {
+        if self.is_less(i, j) {
+            Ordering::Less
+        } else if self.is_less(j, i) {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+
+    fn is_less(&self, i: usize, j: usize) -> bool {
+        (self.is_less.borrow_mut())(&self.keys[i], &self.keys[j])
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.keys.swap(i, j);
+        for column in self.columns.iter_mut() {
+            column.swap(i, j);
+        }
+    }
+}
+
+/// Sorts a key column with PowerSort and reorders every column in `columns` the same way, for
+/// records stored as parallel vectors.
+///
+/// The runs of `keys` are found and merged by [power_sort_target], and every swap it does on
+/// `keys` is done on the other columns too. No records are assembled and no memory proportional to
+/// the number of records is allocated. The columns have no buffer to merge through, so runs are
+/// merged in place, which takes *O*(*n* \* log(*n*)) swaps per merge.
+///
+/// The sort is stable.
+/// # Panics
+/// Panics if a column has a different length than `keys`.
+pub fn power_sort_columns<K, F>(keys: &mut [K], columns: &mut [&mut dyn Permutable], is_less: F)
+where
+    F: FnMut(&K, &K) -> bool,
+{
+    let n = keys.len();
+    for column in columns.iter() {
+        assert_eq!(column.len(), n, "columns must have as many elements as the keys");
+    }
+
+    power_sort_target(&mut Columns { keys, columns, is_less: RefCell::new(is_less) });
+}
+
+#[cfg(test)]
+mod apply_permutation_tests {
+    use super::apply_permutation;
+
+    #[test]
+    fn apply_permutation_test_1() {
+        let mut v = vec!["a", "b", "c", "d", "e"];
+        let mut perm = vec![3, 0, 4, 1, 2];
+        apply_permutation(&mut v, &mut perm);
+        assert_eq!(v, vec!["d", "a", "e", "b", "c"]);
+        assert_eq!(perm, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn apply_permutation_test_2() {
+        let mut v: Vec<String> = (0..100).map(|x| x.to_string()).collect();
+        let mut perm: Vec<usize> = (0..100).rev().collect();
+        apply_permutation(&mut v, &mut perm);
+        assert!(v.iter().rev().map(|x| x.parse::<usize>().unwrap()).eq(0..100));
+    }
+}
+
+#[cfg(test)]
+mod power_sort_columns_tests {
+    use super::power_sort_columns;
+    use crate::sequences::generate_random_sequence_in_range;
+
+    #[test]
+    fn power_sort_columns_test_1() {
+        let mut keys: Vec<u64> = vec![30, 10, 20, 10];
+        let mut vals: Vec<f32> = vec![3.0, 1.0, 2.0, 1.5];
+        let mut ids: Vec<u32> = vec![0, 1, 2, 3];
+
+        power_sort_columns(&mut keys, &mut [&mut vals, &mut ids], |a, b| a < b);
+        assert_eq!(keys, vec![10, 10, 20, 30]);
+        assert_eq!(vals, vec![1.0, 1.5, 2.0, 3.0]);
+        assert_eq!(ids, vec![1, 3, 2, 0]);
+    }
+
+    #[test]
+    // Every column follows the keys, and equal keys keep their order.
+    fn power_sort_columns_test_2() {
+        let mut keys = generate_random_sequence_in_range(1000, 0, 40);
+        let mut ids: Vec<usize> = (0..1000).collect();
+        let mut names: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+
+        let mut expected: Vec<(i32, usize)> = keys.iter().copied().zip(0..).collect();
+        expected.sort_by_key(|x| x.0);
+
+        power_sort_columns(&mut keys, &mut [&mut ids, &mut names], |a, b| a < b);
+        assert!(keys.iter().copied().zip(ids.iter().copied()).eq(expected.into_iter()));
+        assert!(names.iter().zip(keys.iter()).all(|(name, k)| *name == k.to_string()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn power_sort_columns_test_3() {
+        let mut keys = vec![2, 1];
+        let mut short = vec![0];
+        power_sort_columns(&mut keys, &mut [&mut short], |a, b| a < b);
+    }
+}
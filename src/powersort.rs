@@ -273,7 +273,7 @@ where
             if is_less(&last, &v[i]) {
                 // Shift v[i..n-1] to v[i+1..n]
                 unsafe {
-                    ptr::copy_nonoverlapping(
+                    ptr::copy(
                         v.get_unchecked(i), 
                         v.get_unchecked_mut(i + 1), 
                         n-i-1
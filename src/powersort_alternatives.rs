@@ -21,7 +21,7 @@ where
     // Look for runs and merge if possible.
     while s1 + n1 < n {
         let s2 = s1 + n1;
-        let (mut n2, is_increasing) = extend_run_right(v, s2, &mut is_less);
+        let (n2, is_increasing) = extend_run_right(v, s2, &mut is_less);
 
         if !is_increasing {
             v[s2..s2+n2].reverse();
@@ -1,28 +1,29 @@
-use std::ptr;
+use std::convert::Infallible;
+use std::{mem, mem::size_of, ptr, slice};
+
+use crate::error::SortError;
+use crate::permutation::apply_permutation;
 
 pub fn insert_sort<T, F>(v: &mut [T], is_less: &mut F)
 where
     F: FnMut(&T, &T) -> bool
 {
-    let first = unsafe { ptr::read(v.get_unchecked(0)) };
     // We find the correct position to insert `v[0]`.
     // Then we shift the sequence to make space for it and finally copy it
     // in the hole
-    if v.len() >= 2 && is_less(&v[1], &first) {
+    if v.len() >= 2 && is_less(&v[1], &v[0]) {
+        // `first` is a shallow copy of `v[0]`, it must not be dropped as `v` still owns the element.
+        let first = unsafe { mem::ManuallyDrop::new(ptr::read(v.get_unchecked(0))) };
 
         for i in (1..v.len()).rev() {
             if is_less(&v[i], &first) {
-                // Shift v[1..i] to v[0..i-1] by copying
+                let v = v.as_mut_ptr();
                 unsafe {
-                    ptr::copy_nonoverlapping(
-                        v.get_unchecked(1),
-                        v.get_unchecked_mut(0),
-                        i,
-                    );
+                    // Shift v[1..=i] to v[0..i] by copying
+                    ptr::copy(v.add(1), v, i);
+                    // Insert first in the hole
+                    ptr::write(v.add(i), mem::ManuallyDrop::into_inner(first));
                 }
-
-                // Insert first in the hole
-                v[i] = first;
                 break;
             }
         }
@@ -44,24 +45,50 @@ where
     }
 }
 
+/// Merges non-decreasing runs `v[..mid]` and `v[mid..]` using `buf` as temporary storage, and
+/// stores the result into `v[..]`.
+///
+/// # Safety
+///
+/// The two slices must be non-empty and `mid` must be in bounds. Buffer `buf` must be long enough
+/// to hold a copy of the shorter slice. Also, `T` must not be a zero-sized type.
 #[cfg(not(no_global_oom_handling))]
 pub unsafe fn merge<T, F>(v: &mut [T], mid: usize, buf: *mut T, is_less: &mut F)
 where
     F: FnMut(&T, &T) -> bool,
 {
-    use std::mem;
+    unsafe { merge_strided(v, 1, mid, buf, &mut |a: &[T], b: &[T]| is_less(&a[0], &b[0])) };
+}
 
-    let len = v.len();
+/// Like [merge], but every element of the runs is a group of `width` consecutive values of `v`,
+/// such as a row of a matrix, and `mid` is counted in groups. Groups are compared as slices and
+/// always moved whole.
+///
+/// # Safety
+///
+/// `width` must not be zero and must divide `v.len()`. The two runs must be non-empty and `mid`
+/// must be in bounds. Buffer `buf` must be long enough to hold a copy of the shorter run, that is
+/// its length in groups times `width` values. Also, `T` must not be a zero-sized type.
+pub(crate) unsafe fn merge_strided<T, F>(
+    v: &mut [T],
+    width: usize,
+    mid: usize,
+    buf: *mut T,
+    is_less: &mut F,
+) where
+    F: FnMut(&[T], &[T]) -> bool,
+{
+    let len = v.len() / width;
     let v = v.as_mut_ptr();
-    let (v_mid, v_end) = unsafe { (v.add(mid), v.add(len)) };
+    let (v_mid, v_end) = unsafe { (v.add(mid * width), v.add(len * width)) };
 
     let mut hole;
 
     if mid <= len - mid {
         // The left run is shorter.
         unsafe {
-            ptr::copy_nonoverlapping(v, buf, mid);
-            hole = MergeHole { start: buf, end: buf.add(mid), dest: v };
+            ptr::copy_nonoverlapping(v, buf, mid * width);
+            hole = MergeHole { start: buf, end: buf.add(mid * width), dest: v };
         }
 
         // Initially, these pointers point to the beginnings of their arrays.
@@ -73,19 +100,19 @@ where
             // Consume the lesser side.
             // If equal, prefer the left run to maintain stability.
             unsafe {
-                let to_copy = if is_less(&*right, &**left) {
-                    get_and_increment(&mut right)
+                let to_copy = if is_less(group(right, width), group(*left, width)) {
+                    get_and_increment(&mut right, width)
                 } else {
-                    get_and_increment(left)
+                    get_and_increment(left, width)
                 };
-                ptr::copy_nonoverlapping(to_copy, get_and_increment(out), 1);
+                ptr::copy_nonoverlapping(to_copy, get_and_increment(out, width), width);
             }
         }
     } else {
         // The right run is shorter.
         unsafe {
-            ptr::copy_nonoverlapping(v_mid, buf, len - mid);
-            hole = MergeHole { start: buf, end: buf.add(len - mid), dest: v_mid };
+            ptr::copy_nonoverlapping(v_mid, buf, (len - mid) * width);
+            hole = MergeHole { start: buf, end: buf.add((len - mid) * width), dest: v_mid };
         }
 
         // Initially, these pointers point past the ends of their arrays.
@@ -97,26 +124,32 @@ where
             // Consume the greater side.
             // If equal, prefer the right run to maintain stability.
             unsafe {
-                let to_copy = if is_less(&*right.offset(-1), &*left.offset(-1)) {
-                    decrement_and_get(left)
+                let last_right = group(right.sub(width), width);
+                let to_copy = if is_less(last_right, group(left.sub(width), width)) {
+                    decrement_and_get(left, width)
                 } else {
-                    decrement_and_get(right)
+                    decrement_and_get(right, width)
                 };
-                ptr::copy_nonoverlapping(to_copy, decrement_and_get(&mut out), 1);
+                ptr::copy_nonoverlapping(to_copy, decrement_and_get(&mut out, width), width);
             }
         }
     }
     // Finally, `hole` gets dropped. If the shorter run was not fully consumed, whatever remains of
     // it will now be copied into the hole in `v`.
 
-    unsafe fn get_and_increment<T>(ptr: &mut *mut T) -> *mut T {
+    /// Views the group of `width` values starting at `p` as a slice.
+    unsafe fn group<'a, T>(p: *const T, width: usize) -> &'a [T] {
+        unsafe { slice::from_raw_parts(p, width) }
+    }
+
+    unsafe fn get_and_increment<T>(ptr: &mut *mut T, width: usize) -> *mut T {
         let old = *ptr;
-        *ptr = unsafe { ptr.offset(1) };
+        *ptr = unsafe { ptr.add(width) };
         old
     }
 
-    unsafe fn decrement_and_get<T>(ptr: &mut *mut T) -> *mut T {
-        *ptr = unsafe { ptr.offset(-1) };
+    unsafe fn decrement_and_get<T>(ptr: &mut *mut T, width: usize) -> *mut T {
+        *ptr = unsafe { ptr.sub(width) };
         *ptr
     }
 
@@ -193,8 +226,146 @@ pub fn node_power(s1: usize, n1: usize, n2: usize, n: usize) -> usize {
     power
 }
 
-fn capacity(n: usize) -> usize {
-    n.next_power_of_two() + 1
+pub(crate) fn capacity(n: usize) -> usize {
+    // Powers are bounded by log2(n) + 1, so this is enough to never grow the stack.
+    (usize::BITS - n.leading_zeros()) as usize + 2
+}
+
+// Runs less than this value are extended using insertion sort.
+pub(crate) const MIN_RUN_LENGTH: usize = 10;
+// Sequences less than this length are sorted using insertion sort.
+pub(crate) const MAX_INSERTION: usize = 20;
+
+/// Finds the run ending at `end`, makes it weakly increasing and extends it to the minimum run
+/// length using insertion sort. Returns the length of the run.
+pub(crate) fn find_run<T, F>(v: &mut [T], end: usize, is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (mut length, is_increasing) = extend_run_left(v, end, is_less);
+    // Reverse the run if it is decreasing so we only have (weakly) increasing runs.
+    if !is_increasing {
+        v[end - (length - 1)..=end].reverse();
+    }
+
+    while length < MIN_RUN_LENGTH && end - (length - 1) > 0 {
+        insert_sort(&mut v[end - length..=end], is_less);
+        length += 1;
+    }
+
+    length
+}
+
+/// What [power_sort_try_reserve] does when the scratch buffer can't be allocated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScratchFallback {
+    /// Give up and return [SortError::AllocFailed], leaving `v` untouched.
+    Fail,
+    /// Merge runs in place by rotating them, without any scratch memory.
+    InPlace,
+    /// Try scratch buffers of at most the given number of elements, halving the request after
+    /// every failed allocation. Merges whose shorter run doesn't fit are split by rotations.
+    Bounded(usize),
+}
+
+/// Allocates the scratch buffer used for merging `n` elements, following `fallback` if the
+/// allocation fails. The returned buffer may have any capacity between 0 and `n / 2`.
+pub(crate) fn try_scratch<T>(n: usize, fallback: ScratchFallback) -> Result<Vec<T>, SortError> {
+    let mut buf = Vec::new();
+    if buf.try_reserve_exact(n / 2).is_ok() {
+        return Ok(buf);
+    }
+
+    match fallback {
+        ScratchFallback::Fail => return Err(SortError::AllocFailed),
+        ScratchFallback::InPlace => {}
+        ScratchFallback::Bounded(max) => {
+            let mut cap = max.min(n / 2);
+            while cap > 0 && buf.try_reserve_exact(cap).is_err() {
+                cap /= 2;
+            }
+        }
+    }
+
+    Ok(buf)
+}
+
+/// Like [merge], but `buf` only needs to hold `cap` elements.
+///
+/// If the shorter run fits in `buf` this is a regular [merge]. Otherwise the merge is split in two
+/// smaller independent merges by rotating the runs, see `split_merge`. With `cap == 0` the merge
+/// is done entirely in place in *O*(*n* \* log(*n*)) time.
+///
+/// # Safety
+///
+/// `mid` must be in bounds, `buf` must be valid for writes of `cap` elements, and `T` must not be
+/// a zero-sized type.
+pub unsafe fn merge_bounded<T, F>(v: &mut [T], mid: usize, buf: *mut T, cap: usize, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = v.len();
+    if mid == 0 || mid == len {
+        return;
+    }
+
+    if mid.min(len - mid) <= cap {
+        unsafe { merge(v, mid, buf, is_less) };
+        return;
+    }
+
+    if len == 2 {
+        if is_less(&v[1], &v[0]) {
+            v.swap(0, 1);
+        }
+        return;
+    }
+
+    let (left_cut, new_mid) = split_merge(v, mid, is_less);
+
+    unsafe {
+        merge_bounded(&mut v[..new_mid], left_cut, buf, cap, is_less);
+        merge_bounded(&mut v[new_mid..], mid - left_cut, buf, cap, is_less);
+    }
+}
+
+/// Splits the merge of `v[..mid]` and `v[mid..]` into two smaller independent merges.
+///
+/// Rotating `v[left_cut..right_cut]` by [merge_cuts] leaves `v[..new_mid]` to be merged at
+/// `left_cut`, and `v[new_mid..]` to be merged at `mid - left_cut`. Returns `(left_cut, new_mid)`.
+/// Both runs must be non-empty and `v` must have at least 3 elements.
+pub(crate) fn split_merge<T, F>(v: &mut [T], mid: usize, is_less: &mut F) -> (usize, usize)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (left_cut, right_cut) = merge_cuts(v, mid, is_less);
+
+    v[left_cut..right_cut].rotate_left(mid - left_cut);
+    (left_cut, left_cut + (right_cut - mid))
+}
+
+/// Finds where to split the merge of `v[..mid]` and `v[mid..]`, see [split_merge].
+///
+/// The longer run is cut in half, and the position of its middle element in the shorter run is
+/// found by binary search. Returns `(left_cut, right_cut)`: rotating `v[left_cut..right_cut]` to
+/// the left by `mid - left_cut` makes the merges on both sides of it independent.
+pub(crate) fn merge_cuts<T, F>(v: &[T], mid: usize, is_less: &mut F) -> (usize, usize)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = v.len();
+
+    if mid >= len - mid {
+        let left_cut = mid / 2;
+        // Elements of the right run strictly less than `v[left_cut]` must end up before it.
+        let right_cut = mid + v[mid..].partition_point(|x| is_less(x, &v[left_cut]));
+        (left_cut, right_cut)
+    } else {
+        let right_cut = mid + (len - mid) / 2;
+        // Elements of the left run not greater than `v[right_cut]` must stay before it.
+        let left_cut = v[..mid].partition_point(|x| !is_less(&v[right_cut], x));
+        (left_cut, right_cut)
+    }
 }
 
 /// This merge sort borrows some (but not all) ideas from TimSort, which is described in detail
@@ -214,13 +385,6 @@ pub fn power_sort<T, F>(v: &mut [T], mut is_less: F)
 where 
     F: FnMut(&T, &T) -> bool,
 {
-    use std::mem::size_of;
-
-    // Runs less than this value are extended using insertion sort.
-    const MIN_RUN_LENGTH: usize = 10;
-    // Sequences less than this length are sorted using insertion sort.
-    const MAX_INSERTION: usize = 20;
-
     if size_of::<T>() == 0 {
         return;
     }
@@ -233,48 +397,225 @@ where
         return;
     }
 
-    // Stack for storing runs.
-    let mut runs: Vec<Run> = Vec::with_capacity(capacity(n));
+    // Buffer for merging runs
+    let mut buf = Vec::with_capacity(n / 2);
 
-    let mut e1 = n-1;
-    let (mut n1, is_increasing) = extend_run_left(v, e1, &mut is_less);
-    // Reverse the run if it is decreasing so we only have (weakly) increasing runs.
-    if !is_increasing {
-        v[e1-(n1-1)..=e1].reverse();
+    unsafe { power_sort_buffer(v, &mut is_less, buf.as_mut_ptr(), n / 2) };
+}
+
+/// Like [power_sort], but reports a failure to allocate the scratch buffer instead of aborting.
+///
+/// If the buffer of `v.len() / 2` elements can't be allocated, `fallback` decides whether to
+/// return [SortError::AllocFailed] or to keep sorting with a smaller buffer or none at all.
+/// Merging with less scratch memory is slower, but the result is the same.
+pub fn power_sort_try_reserve<T, F>(
+    v: &mut [T],
+    mut is_less: F,
+    fallback: ScratchFallback,
+) -> Result<(), SortError>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    if size_of::<T>() == 0 {
+        return Ok(());
+    }
+
+    let n = v.len();
+
+    if n < MAX_INSERTION {
+        insertion_sort(v, &mut is_less);
+        return Ok(());
+    }
+
+    let mut buf = try_scratch(n, fallback)?;
+    let cap = buf.capacity();
+
+    unsafe { power_sort_buffer(v, &mut is_less, buf.as_mut_ptr(), cap) };
+    Ok(())
+}
+
+/// Sorts `v` by a key with PowerSort, calling `f` exactly once per element.
+///
+/// The keys are computed into a buffer of `(key, index)` pairs, which is sorted by key with
+/// [power_sort]. The sorted indices are then applied to `v` in place with
+/// [apply_permutation]. This is worth it when `f` is expensive, like parsing or hashing, and
+/// matches `slice::sort_by_cached_key`.
+///
+/// The sort is stable. It needs *O*(*n*) extra memory for the keys and indices.
+pub fn power_sort_by_cached_key<T, K, F>(v: &mut [T], f: F)
+where
+    K: Ord,
+    F: FnMut(&T) -> K,
+{
+    let n = v.len();
+    if n < 2 {
+        return;
+    }
+
+    let mut indices: Vec<(K, usize)> = v.iter().map(f).zip(0..).collect();
+    power_sort(&mut indices, |a, b| a.0 < b.0);
+
+    let mut perm: Vec<usize> = indices.into_iter().map(|(_, index)| index).collect();
+    apply_permutation(v, &mut perm);
+}
+
+/// Sorts `v` by a key borrowed from each element with PowerSort.
+///
+/// Unlike `slice::sort_by_key`, `f` may return a reference into the element, such as a `&str` to
+/// one of its fields, so sorting by a string or slice field needs no clone or allocation. The key
+/// is borrowed again for every comparison, so it should be cheap to get to.
+///
+/// The sort is stable.
+pub fn power_sort_by_ref_key<T, K, F>(v: &mut [T], mut f: F)
+where
+    K: Ord + ?Sized,
+    F: FnMut(&T) -> &K,
+{
+    power_sort(v, |a, b| f(a) < f(b));
+}
+
+/// The PowerSort merge loop shared by [power_sort] and [power_sort_try_reserve].
+/// `buf` holds `cap` elements, merges that need more scratch memory go through [merge_bounded].
+///
+/// # Safety
+///
+/// `v` must not be empty, `buf` must be valid for writes of `cap` elements, and `T` must not be a
+/// zero-sized type.
+unsafe fn power_sort_buffer<T, F>(v: &mut [T], is_less: &mut F, buf: *mut T, cap: usize)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let Ok(()) = unsafe { power_sort_hooked(v, is_less, buf, cap, &mut ()) };
+}
+
+/// Represents a run of elements in a vector.
+#[derive(Clone, Copy, Debug)]
+struct Run {
+    end: usize,
+    length: usize,
+    power: usize
+}
+
+/// Callbacks of [power_sort_hooked], for the sorting functions that need to look at the runs as
+/// they are found and merged. Returning an error from a hook stops the sort, leaving `v` as a
+/// permutation of its input with the merges done so far applied.
+pub(crate) trait SortHooks<T> {
+    type Stop;
+
+    /// Called after `v[start..end]` is found as a run, made weakly increasing and extended to the
+    /// minimum run length.
+    fn run_found<F>(
+        &mut self,
+        _v: &[T],
+        _start: usize,
+        _end: usize,
+        _pending: &Pending,
+        _is_less: &mut F,
+    ) -> Result<(), Self::Stop>
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        Ok(())
     }
 
-    // Extend the first run to the left until it is long enough.
-    while n1 < MIN_RUN_LENGTH && e1-(n1-1) > 0  {
-        insert_sort(&mut v[e1-n1..=e1], &mut is_less);
-        n1 += 1;
+    /// Called after two runs are merged into `v[start..end]`.
+    fn merged<F>(
+        &mut self,
+        _v: &[T],
+        _start: usize,
+        _end: usize,
+        _pending: &Pending,
+        _is_less: &mut F,
+    ) -> Result<(), Self::Stop>
+    where
+        F: FnMut(&T, &T) -> bool,
+    {
+        Ok(())
+    }
+}
+
+impl<T> SortHooks<T> for () {
+    type Stop = Infallible;
+}
+
+/// The state of a [power_sort_hooked] call when one of its hooks is called.
+pub(crate) struct Pending<'a> {
+    /// The runs waiting on the stack.
+    runs: &'a [Run],
+    /// The number of elements left of every run found so far.
+    unscanned: usize,
+    /// The length of the run being merged, and the most merges it can still take part in.
+    current: (usize, usize),
+    /// The length of the run found last, when it isn't the one being merged.
+    next: usize,
+    /// The most merges an element can take part in, more than the power of any boundary.
+    max_merges: usize,
+}
+
+impl Pending<'_> {
+    /// Returns an upper bound on the work left, counting one unit per element for finding the
+    /// runs and the length of every merge.
+    ///
+    /// The powers of the merges a run takes part in decrease towards the root of the merge tree,
+    /// so a run waiting on the stack with power `p` takes part in at most `p` more merges. The
+    /// bound never grows as the sort goes on, and is zero once it is done.
+    pub(crate) fn remaining_work(&self) -> usize {
+        let stacked: usize = self.runs.iter().map(|run| run.length * run.power).sum();
+        self.unscanned * (1 + self.max_merges)
+            + stacked
+            + self.current.0 * self.current.1
+            + self.next * self.max_merges
     }
+}
+
+/// The PowerSort merge loop, calling `hooks` after every run found and every merge.
+///
+/// # Safety
+///
+/// Same as [power_sort_buffer].
+pub(crate) unsafe fn power_sort_hooked<T, F, H>(
+    v: &mut [T],
+    is_less: &mut F,
+    buf: *mut T,
+    cap: usize,
+    hooks: &mut H,
+) -> Result<(), H::Stop>
+where
+    F: FnMut(&T, &T) -> bool,
+    H: SortHooks<T>,
+{
+    let n = v.len();
+    let max_merges = capacity(n);
+
+    // Stack for storing runs.
+    let mut runs: Vec<Run> = Vec::with_capacity(capacity(n));
+
+    let mut e1 = n-1;
+    let mut n1 = find_run(v, e1, is_less);
 
     // Start of the run
     let mut s1 = e1 - (n1 - 1);
 
-    // Buffer for merging runs
-    let mut buf = Vec::with_capacity(n / 2);
+    // A run covering all of `v` takes part in no merges.
+    let merges = if s1 == 0 { 0 } else { max_merges };
+    let pending =
+        Pending { runs: &runs, unscanned: s1, current: (n1, merges), next: 0, max_merges };
+    hooks.run_found(v, s1, e1 + 1, &pending, is_less)?;
 
     // Look for runs and merge if possible.
     while s1 > 0 {
         // Find second run.
         let e2 = s1 - 1;
-        let (mut n2, is_increasing) = extend_run_left(v, e2, &mut is_less);
-
-        if !is_increasing {
-            v[e2-(n2-1)..=e2].reverse();
-        }
-        
-        while n2 < MIN_RUN_LENGTH && e2-(n2-1) > 0 {
-            insert_sort(&mut v[e2-n2..=e2], &mut is_less);
-            n2 += 1;
-        }
-
+        let n2 = find_run(v, e2, is_less);
         let s2 = e2 - (n2 - 1);
 
         // Compute power between runs.
         let power = node_power(s1, n1, n2, n);
 
+        let pending =
+            Pending { runs: &runs, unscanned: s2, current: (n1, power), next: n2, max_merges };
+        hooks.run_found(v, s2, e2 + 1, &pending, is_less)?;
+
         // Merge if possible.
         while let Some(run) = runs.last() {
             // If the top run's power is not greater than the power of the new run, merge them.
@@ -282,10 +623,19 @@ where
                 let run = runs.pop().unwrap();
 
                 // Merge the two runs.
-                unsafe { merge(&mut v[s1..=run.end], n1, buf.as_mut_ptr(), &mut is_less) };
+                unsafe { merge_bounded(&mut v[s1..=run.end], n1, buf, cap, is_less) };
                 e1 = run.end;
                 n1 += run.length;
                 s1 = e1 - (n1 - 1);
+
+                let pending = Pending {
+                    runs: &runs,
+                    unscanned: s2,
+                    current: (n1, power),
+                    next: n2,
+                    max_merges,
+                };
+                hooks.merged(v, s1, e1 + 1, &pending, is_less)?;
             } else {
                 // Else, exit
                 break;
@@ -306,23 +656,129 @@ where
 
     // Merge remaining runs.
     while let Some(run) = runs.pop() {
-        unsafe { merge(&mut v[s1..=run.end], n1, buf.as_mut_ptr(), &mut is_less) };
+        unsafe { merge_bounded(&mut v[s1..=run.end], n1, buf, cap, is_less) };
         e1 = run.end;
         n1 += run.length;
         s1 = e1 - (n1 - 1);
+
+        // The rest of the stack is merged into this run one by one.
+        let pending =
+            Pending { runs: &runs, unscanned: 0, current: (n1, runs.len()), next: 0, max_merges };
+        hooks.merged(v, s1, e1 + 1, &pending, is_less)?;
     }
 
+    Ok(())
+}
 
-    /// Represents a run of elements in a vector.
-    #[derive(Clone, Copy, Debug)]
-    struct Run {
-        end: usize,
-        length: usize,
-        power: usize
-    }
+/// A run on a [RunStack], with its position among the runs pushed so far.
+#[derive(Clone, Debug)]
+struct StackedRun<R> {
+    run: R,
+    start: usize,
+    length: usize,
+    /// The power of the boundary between this run and the next one, unused for the top run.
+    power: usize,
 }
 
+/// The stack of runs of a PowerSort, for the sorts that find or merge runs in their own way.
+///
+/// Runs are pushed in the order they are found, and the runs below a new one are merged while the
+/// power rule says so, like [power_sort] does. What a run is and how two of them are merged is left
+/// to the caller: the merge function gets the earlier run, which must hold the result, and the
+/// later one, which is dropped afterwards. Every run stays on the stack while it's merged, so a
+/// caller that keeps the stack in a drop guard gets them all back if a merge panics.
+#[derive(Clone, Debug)]
+pub(crate) struct RunStack<R> {
+    runs: Vec<StackedRun<R>>,
+    /// The total length powers are computed for.
+    n: usize,
+}
+
+impl<R> RunStack<R> {
+    /// Creates an empty stack for runs adding up to at most `n` elements.
+    pub(crate) fn new(n: usize) -> Self {
+        RunStack { runs: Vec::with_capacity(capacity(n) + 1), n }
+    }
+
+    /// Returns the runs on the stack, from the earliest to the latest one.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &R> {
+        self.runs.iter().map(|run| &run.run)
+    }
+
+    /// Returns the runs on the stack mutably, from the earliest to the latest one.
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut R> {
+        self.runs.iter_mut().map(|run| &mut run.run)
+    }
+
+    /// Doubles the total length powers are computed for until it's at least `n`.
+    ///
+    /// Doubling the length adds one to the power of every boundary, so the powers on the stack
+    /// stay comparable with the ones computed afterwards.
+    pub(crate) fn grow(&mut self, n: usize) {
+        while self.n < n {
+            self.n *= 2;
+            self.runs.iter_mut().for_each(|run| run.power += 1);
+        }
+    }
+
+    /// Pushes `run` of `length` elements, then merges the runs below it while the power of the
+    /// boundary under them is greater than the one before `run`.
+    pub(crate) fn push<M>(&mut self, run: R, length: usize, mut merge: M)
+    where
+        M: FnMut(&mut R, &mut R),
+    {
+        let Some(top) = self.runs.last() else {
+            self.runs.push(StackedRun { run, start: 0, length, power: 0 });
+            return;
+        };
+
+        // Compute power between runs.
+        let start = top.start + top.length;
+        let power = node_power(top.start, top.length, length, self.n);
+        self.runs.push(StackedRun { run, start, length, power: 0 });
+
+        // Merge if possible.
+        while self.runs.len() > 2 && self.runs[self.runs.len() - 3].power > power {
+            self.merge_at(self.runs.len() - 3, &mut merge);
+        }
+
+        let len = self.runs.len();
+        self.runs[len - 2].power = power;
+    }
+
+    /// Merges the runs on the stack until at most one is left.
+    pub(crate) fn collapse<M>(&mut self, mut merge: M)
+    where
+        M: FnMut(&mut R, &mut R),
+    {
+        while self.runs.len() > 1 {
+            self.merge_at(self.runs.len() - 2, &mut merge);
+        }
+    }
+
+    /// Merges all runs on the stack and returns the result, or `None` if the stack is empty.
+    pub(crate) fn finish<M>(&mut self, merge: M) -> Option<R>
+    where
+        M: FnMut(&mut R, &mut R),
+    {
+        self.collapse(merge);
+        self.runs.pop().map(|run| run.run)
+    }
 
+    /// Merges the run at `i` with the one after it.
+    fn merge_at<M>(&mut self, i: usize, merge: &mut M)
+    where
+        M: FnMut(&mut R, &mut R),
+    {
+        let (left, right) = self.runs[i..].split_at_mut(1);
+        merge(&mut left[0].run, &mut right[0].run);
+        left[0].length += right[0].length;
+        left[0].power = right[0].power;
+        self.runs.remove(i + 1);
+    }
+}
+
+#[cfg(test)]
 mod powesort_tests {
     use super::power_sort;
 
@@ -354,4 +810,200 @@ mod powesort_tests {
         power_sort(&mut v, |a, b| a < b);
         assert_eq!(v, sorted);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod run_stack_tests {
+    use std::ops::Range;
+
+    use super::{capacity, RunStack};
+
+    #[test]
+    // Only neighbouring runs are merged, and the stack stays logarithmic.
+    fn run_stack_test_1() {
+        let lengths = [5, 1, 1, 8, 3, 3, 20, 2, 1, 1, 1, 40, 7];
+        let n = lengths.iter().sum();
+        let mut runs: RunStack<Range<usize>> = RunStack::new(n);
+
+        let merge = |left: &mut Range<usize>, right: &mut Range<usize>| {
+            assert_eq!(left.end, right.start);
+            left.end = right.end;
+        };
+
+        let mut start = 0;
+        for length in lengths {
+            runs.push(start..start + length, length, merge);
+            assert!(runs.runs.len() <= capacity(n) + 1);
+            start += length;
+        }
+        assert_eq!(runs.finish(merge), Some(0..n));
+    }
+
+    #[test]
+    fn run_stack_test_2() {
+        let mut runs: RunStack<Vec<i32>> = RunStack::new(0);
+        assert_eq!(runs.finish(|left, right| left.append(right)), None);
+    }
+}
+
+#[cfg(test)]
+mod merge_bounded_tests {
+    use super::merge_bounded;
+
+    // Merges `v[..mid]` and `v[mid..]` with a buffer of `cap` elements.
+    fn merge_with_cap(v: &mut [(i32, usize)], mid: usize, cap: usize) {
+        let mut buf: Vec<(i32, usize)> = Vec::with_capacity(cap);
+        unsafe { merge_bounded(v, mid, buf.as_mut_ptr(), cap, &mut |a, b| a.0 < b.0) };
+    }
+
+    fn tagged(keys: &[i32]) -> Vec<(i32, usize)> {
+        keys.iter().copied().zip(0..).collect()
+    }
+
+    #[test]
+    fn merge_bounded_test_1() {
+        let mut v = tagged(&[1, 3, 5, 7, 9, 11, 2, 4]);
+        merge_with_cap(&mut v, 6, 0);
+        let keys: Vec<i32> = v.iter().map(|x| x.0).collect();
+        assert_eq!(keys, vec![1, 2, 3, 4, 5, 7, 9, 11]);
+    }
+
+    #[test]
+    // Equal elements of the left run must stay before the ones of the right run.
+    fn merge_bounded_test_2() {
+        for cap in [0, 1, 2, 3, 16] {
+            let mut v = tagged(&[1, 2, 2, 2, 5, 5, 0, 2, 2, 5, 6, 7, 8]);
+            let mut expected = v.clone();
+            expected.sort_by_key(|x| x.0);
+
+            merge_with_cap(&mut v, 6, cap);
+            assert_eq!(v, expected);
+        }
+    }
+
+    #[test]
+    // Empty runs are left untouched.
+    fn merge_bounded_test_3() {
+        let mut v = tagged(&[3, 1, 2]);
+        merge_with_cap(&mut v, 0, 0);
+        merge_with_cap(&mut v, 3, 0);
+        assert_eq!(v, tagged(&[3, 1, 2]));
+    }
+}
+
+#[cfg(test)]
+mod power_sort_try_reserve_tests {
+    use super::{power_sort_buffer, power_sort_try_reserve, ScratchFallback};
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    fn power_sort_try_reserve_test_1() {
+        let mut v = generate_m_runs(500, 50);
+        let mut sorted = v.clone();
+        sorted.sort();
+
+        assert_eq!(power_sort_try_reserve(&mut v, |a, b| a < b, ScratchFallback::Fail), Ok(()));
+        assert_eq!(v, sorted);
+    }
+
+    #[test]
+    // Sorting with less scratch memory than needed must still be stable.
+    fn power_sort_try_reserve_test_2() {
+        let keys = generate_random_sequence_in_range(1000, 0, 20);
+        let v: Vec<(i32, usize)> = keys.into_iter().zip(0..).collect();
+        let mut sorted = v.clone();
+        sorted.sort_by_key(|x| x.0);
+
+        for cap in [0, 1, 7, 100] {
+            let mut v = v.clone();
+            let mut buf: Vec<(i32, usize)> = Vec::with_capacity(cap);
+            unsafe { power_sort_buffer(&mut v, &mut |a, b| a.0 < b.0, buf.as_mut_ptr(), cap) };
+            assert_eq!(v, sorted);
+        }
+    }
+
+    #[test]
+    // Owned values must be moved, not duplicated or dropped, while merging in place.
+    fn power_sort_try_reserve_test_3() {
+        let mut v: Vec<String> = generate_m_runs(300, 20).iter().map(|x| x.to_string()).collect();
+        let mut sorted = v.clone();
+        sorted.sort();
+
+        let mut buf: Vec<String> = Vec::new();
+        unsafe { power_sort_buffer(&mut v, &mut |a, b| a < b, buf.as_mut_ptr(), 0) };
+        assert_eq!(v, sorted);
+    }
+}
+
+#[cfg(test)]
+mod power_sort_by_cached_key_tests {
+    use super::power_sort_by_cached_key;
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    // The key is computed once per element, and the result matches the standard library.
+    fn power_sort_by_cached_key_test_1() {
+        let mut v: Vec<String> = generate_m_runs(1000, 30).iter().map(|x| x.to_string()).collect();
+        let mut expected = v.clone();
+        expected.sort_by_cached_key(|x| x.parse::<i32>().unwrap());
+
+        let mut calls = 0;
+        power_sort_by_cached_key(&mut v, |x| {
+            calls += 1;
+            x.parse::<i32>().unwrap()
+        });
+        assert_eq!(calls, 1000);
+        assert_eq!(v, expected);
+    }
+
+    #[test]
+    // Equal keys keep their order.
+    fn power_sort_by_cached_key_test_2() {
+        let mut v: Vec<(i32, usize)> = generate_random_sequence_in_range(800, 0, 15).into_iter().zip(0..).collect();
+        let mut expected = v.clone();
+        expected.sort_by_key(|x| x.0);
+
+        power_sort_by_cached_key(&mut v, |x| x.0);
+        assert_eq!(v, expected);
+    }
+}
+
+#[cfg(test)]
+mod power_sort_by_ref_key_tests {
+    use super::power_sort_by_ref_key;
+    use crate::sequences::generate_random_sequence_in_range;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Record {
+        name: String,
+        tags: Vec<u8>,
+        id: usize,
+    }
+
+    fn records() -> Vec<Record> {
+        let keys = generate_random_sequence_in_range(600, 0, 40);
+        keys.iter()
+            .zip(0..)
+            .map(|(&k, id)| Record { name: format!("name{}", k), tags: vec![(k % 7) as u8, k as u8], id })
+            .collect()
+    }
+
+    #[test]
+    fn power_sort_by_ref_key_test_1() {
+        let mut v = records();
+        let mut expected = v.clone();
+        expected.sort_by(|a, b| a.name.cmp(&b.name));
+
+        power_sort_by_ref_key(&mut v, |r| r.name.as_str());
+        assert_eq!(v, expected);
+    }
+
+    #[test]
+    // Sorting by a borrowed slice keeps records with equal keys in order.
+    fn power_sort_by_ref_key_test_2() {
+        let mut v = records();
+        power_sort_by_ref_key(&mut v, |r| &r.tags[..]);
+
+        assert!(v.windows(2).all(|w| w[0].tags < w[1].tags || (w[0].tags == w[1].tags && w[0].id < w[1].id)));
+    }
+}
@@ -0,0 +1,183 @@
+//! Sorting the rows of a matrix stored as a flat, row-major slice.
+
+use std::mem::size_of;
+use std::ops::Range;
+
+use crate::powersort_final::{merge_strided, RunStack, MAX_INSERTION, MIN_RUN_LENGTH};
+
+/// Returns row `i` of `v`.
+fn row<T>(v: &[T], width: usize, i: usize) -> &[T] {
+    &v[i * width..(i + 1) * width]
+}
+
+/// Reverses the order of the rows in `v`.
+fn reverse_rows<T>(v: &mut [T], width: usize) {
+    let rows = v.len() / width;
+    for i in 0..rows / 2 {
+        let (front, back) = v.split_at_mut((rows - 1 - i) * width);
+        front[i * width..(i + 1) * width].swap_with_slice(&mut back[..width]);
+    }
+}
+
+/// Inserts the first row of `v` into the sorted rows that follow it, by rotating it into place.
+fn insert_head<T, F>(v: &mut [T], width: usize, is_less: &mut F)
+where
+    F: FnMut(&[T], &[T]) -> bool,
+{
+    let rows = v.len() / width;
+    let mut i = 1;
+    while i < rows && is_less(row(v, width, i), row(v, width, 0)) {
+        i += 1;
+    }
+    v[..i * width].rotate_left(width);
+}
+
+/// Returns the length in rows of the run ending at row `end`, and whether it is weakly increasing.
+/// See [extend_run_left](crate::powersort_final::extend_run_left).
+fn extend_run_left<T, F>(v: &[T], width: usize, end: usize, is_less: &mut F) -> (usize, bool)
+where
+    F: FnMut(&[T], &[T]) -> bool,
+{
+    if end == 0 {
+        return (1, true);
+    }
+
+    let is_increasing = !is_less(row(v, width, end), row(v, width, end - 1));
+    let mut i = end;
+    while i > 0 && is_less(row(v, width, i), row(v, width, i - 1)) != is_increasing {
+        i -= 1;
+    }
+
+    (end - i + 1, is_increasing)
+}
+
+/// Finds the run ending at row `end`, makes it weakly increasing and extends it to the minimum run
+/// length using insertion sort. Returns the length of the run in rows.
+fn find_run<T, F>(v: &mut [T], width: usize, end: usize, is_less: &mut F) -> usize
+where
+    F: FnMut(&[T], &[T]) -> bool,
+{
+    let (mut length, is_increasing) = extend_run_left(v, width, end, is_less);
+    // Reverse the run if it is decreasing so we only have (weakly) increasing runs.
+    if !is_increasing {
+        reverse_rows(&mut v[(end + 1 - length) * width..(end + 1) * width], width);
+    }
+
+    while length < MIN_RUN_LENGTH && end + 1 > length {
+        insert_head(&mut v[(end - length) * width..(end + 1) * width], width, is_less);
+        length += 1;
+    }
+
+    length
+}
+
+/// Sorts the rows of a row-major matrix with PowerSort.
+///
+/// `data` is split into rows of `width` consecutive elements, and `is_less` compares two rows. Use
+/// `|a, b| a < b` to sort rows lexicographically, or compare a key such as `a[2] < b[2]` to sort
+/// them by a column. Runs of rows are found and merged in
+/// [node_power](crate::powersort_final::node_power) order like
+/// [power_sort](crate::powersort_final::power_sort) does for single elements, and merges copy
+/// whole rows at a time, so no `Vec<Vec<T>>` is ever built.
+///
+/// The sort is stable.
+/// # Panics
+/// Panics if `width` is zero or doesn't divide `data.len()`.
+pub fn power_sort_rows<T, F>(data: &mut [T], width: usize, mut is_less: F)
+where
+    F: FnMut(&[T], &[T]) -> bool,
+{
+    assert!(
+        width > 0 && data.len().is_multiple_of(width),
+        "the length of the data must be a multiple of the width"
+    );
+
+    let n = data.len() / width;
+    if size_of::<T>() == 0 || n < 2 {
+        return;
+    }
+    let v = data;
+
+    // Use insertion sort for small sequences as it is faster.
+    if n < MAX_INSERTION {
+        for i in (0..n - 1).rev() {
+            insert_head(&mut v[i * width..], width, &mut is_less);
+        }
+        return;
+    }
+
+    // Stack for storing runs, as ranges of rows. Runs are found from the right, so a run pushed
+    // later lies to the left of the ones before it.
+    let mut runs: RunStack<Range<usize>> = RunStack::new(n);
+
+    // Buffer for merging runs, half of the rows.
+    let mut scratch: Vec<T> = Vec::with_capacity(n / 2 * width);
+    let buf = scratch.as_mut_ptr();
+
+    // Look for runs and merge if possible.
+    let mut end = n;
+    while end > 0 {
+        let length = find_run(v, width, end - 1, &mut is_less);
+        runs.push(end - length..end, length, |right, left| {
+            let rows = &mut v[left.start * width..right.end * width];
+            unsafe { merge_strided(rows, width, left.len(), buf, &mut is_less) };
+            right.start = left.start;
+        });
+        end -= length;
+    }
+
+    // Merge remaining runs.
+    runs.finish(|right, left| {
+        let rows = &mut v[left.start * width..right.end * width];
+        unsafe { merge_strided(rows, width, left.len(), buf, &mut is_less) };
+        right.start = left.start;
+    });
+}
+
+#[cfg(test)]
+mod power_sort_rows_tests {
+    use super::power_sort_rows;
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    fn power_sort_rows_test_1() {
+        let mut data = vec![3, 1, 2, 1, 1, 5, 0, 9, 9, 1, 1, 4];
+        power_sort_rows(&mut data, 3, |a, b| a < b);
+        assert_eq!(data, vec![0, 9, 9, 1, 1, 4, 1, 1, 5, 3, 1, 2]);
+    }
+
+    #[test]
+    // Rows sorted lexicographically match sorting a vector of rows.
+    fn power_sort_rows_test_2() {
+        let mut data = generate_random_sequence_in_range(4000, 0, 4);
+        let mut rows: Vec<Vec<i32>> = data.chunks(4).map(|r| r.to_vec()).collect();
+        rows.sort();
+
+        power_sort_rows(&mut data, 4, |a, b| a < b);
+        assert_eq!(data, rows.concat());
+    }
+
+    #[test]
+    // Sorting by one column keeps rows with equal keys in order, and owned values are moved
+    // without being duplicated or dropped.
+    fn power_sort_rows_test_3() {
+        let keys = generate_m_runs(900, 30);
+        let mut data: Vec<String> = keys
+            .iter()
+            .enumerate()
+            .flat_map(|(i, k)| [i.to_string(), format!("{:05}", k)])
+            .collect();
+        let mut rows: Vec<Vec<String>> = data.chunks(2).map(|r| r.to_vec()).collect();
+        rows.sort_by(|a, b| a[1].cmp(&b[1]));
+
+        power_sort_rows(&mut data, 2, |a, b| a[1] < b[1]);
+        assert_eq!(data, rows.concat());
+    }
+
+    #[test]
+    #[should_panic]
+    fn power_sort_rows_test_4() {
+        let mut data = vec![1, 2, 3, 4, 5];
+        power_sort_rows(&mut data, 2, |a, b| a < b);
+    }
+}
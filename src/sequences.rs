@@ -189,4 +189,51 @@ pub fn generate_m_runs(length: usize, m: u32) -> Vec<i32> {
     }
 
     sequence
-}
\ No newline at end of file
+}
+
+/// Generates `length` random URL-like strings. They are drawn from a handful of hosts and path
+/// segments, so many of them share long prefixes.
+/// # Arguments
+/// - `length`: The number of strings to generate.
+/// # Returns
+/// A vector of `length` strings like `https://www.example.org/docs/guide/1234`.
+pub fn generate_urls(length: usize) -> Vec<String> {
+    const HOSTS: [&str; 4] = ["www.example.org", "www.example.com", "api.example.com", "static.example.net"];
+    const SEGMENTS: [&str; 6] = ["docs", "guide", "users", "assets", "v1", "v2"];
+
+    let mut rng = thread_rng();
+    let ids = Uniform::new(0, 100_000);
+
+    (0..length)
+        .map(|_| {
+            let host = HOSTS.choose(&mut rng).unwrap();
+            let first = SEGMENTS.choose(&mut rng).unwrap();
+            let second = SEGMENTS.choose(&mut rng).unwrap();
+            format!("https://{}/{}/{}/{}", host, first, second, rng.sample(ids))
+        })
+        .collect()
+}
+
+/// Generates `length` random file-path-like strings, nested a few directories deep under a small
+/// set of common roots. See [generate_urls].
+/// # Arguments
+/// - `length`: The number of strings to generate.
+/// # Returns
+/// A vector of `length` strings like `/home/alice/projects/powersort/src/merge/file_42.rs`.
+pub fn generate_paths(length: usize) -> Vec<String> {
+    const USERS: [&str; 3] = ["alice", "bob", "carol"];
+    const PROJECTS: [&str; 4] = ["powersort", "timsort", "website", "notes"];
+    const DIRECTORIES: [&str; 5] = ["src", "tests", "benches", "docs", "target"];
+
+    let mut rng = thread_rng();
+    let files = Uniform::new(0, 1000);
+
+    (0..length)
+        .map(|_| {
+            let user = USERS.choose(&mut rng).unwrap();
+            let project = PROJECTS.choose(&mut rng).unwrap();
+            let directory = DIRECTORIES.choose(&mut rng).unwrap();
+            format!("/home/{}/projects/{}/{}/file_{}.rs", user, project, directory, rng.sample(files))
+        })
+        .collect()
+}
@@ -2,6 +2,9 @@ use core::mem;
 use core::mem::size_of;
 use core::ptr;
 
+use crate::error::SortError;
+use crate::powersort_final::{merge_bounded, try_scratch, ScratchFallback};
+
 /// Inserts `&v[0]` into pre-sorted sequence `v[1..]` so that whole `v[..]` becomes sorted.
 ///
 /// This is the integral subroutine of insertion sort.
@@ -209,6 +212,11 @@ pub fn node_power(s1: usize, n1: usize, n2: usize, n: usize) -> u32 {
     power
 }
 
+// Slices of up to this length get sorted using insertion sort.
+const MAX_INSERTION: usize = 20;
+// Very short runs are extended using insertion sort to span at least this many elements.
+const MIN_RUN: usize = 10;
+
 /// This merge sort borrows some (but not all) ideas from TimSort, which is described in detail
 /// [here](https://github.com/python/cpython/blob/main/Objects/listsort.txt).
 ///
@@ -226,11 +234,6 @@ pub fn merge_sort<T, F>(v: &mut [T], mut is_less: F)
 where
     F: FnMut(&T, &T) -> bool,
 {
-    // Slices of up to this length get sorted using insertion sort.
-    const MAX_INSERTION: usize = 20;
-    // Very short runs are extended using insertion sort to span at least this many elements.
-    const MIN_RUN: usize = 10;
-
     // Sorting has no meaningful behavior on zero-sized types.
     if size_of::<T>() == 0 {
         return;
@@ -254,6 +257,54 @@ where
     // which will always have length at most `len / 2`.
     let mut buf = Vec::with_capacity(len / 2);
 
+    unsafe { merge_sort_buffer(v, &mut is_less, buf.as_mut_ptr(), len / 2) };
+}
+
+/// Like [merge_sort], but reports a failure to allocate the scratch buffer instead of aborting.
+/// See [power_sort_try_reserve](crate::powersort_final::power_sort_try_reserve) for the meaning
+/// of `fallback`.
+pub fn merge_sort_try_reserve<T, F>(
+    v: &mut [T],
+    mut is_less: F,
+    fallback: ScratchFallback,
+) -> Result<(), SortError>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    if size_of::<T>() == 0 {
+        return Ok(());
+    }
+
+    let len = v.len();
+
+    if len <= MAX_INSERTION {
+        if len >= 2 {
+            for i in (0..len - 1).rev() {
+                insert_head(&mut v[i..], &mut is_less);
+            }
+        }
+        return Ok(());
+    }
+
+    let mut buf = try_scratch(len, fallback)?;
+    let cap = buf.capacity();
+
+    unsafe { merge_sort_buffer(v, &mut is_less, buf.as_mut_ptr(), cap) };
+    Ok(())
+}
+
+/// The run detection and merge loop of [merge_sort], using `buf` of `cap` elements as scratch
+/// memory.
+///
+/// # Safety
+///
+/// `buf` must be valid for writes of `cap` elements and `T` must not be a zero-sized type.
+unsafe fn merge_sort_buffer<T, F>(v: &mut [T], is_less: &mut F, buf: *mut T, cap: usize)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let len = v.len();
+
     // In order to identify natural runs in `v`, we traverse it backwards. That might seem like a
     // strange decision, but consider the fact that merges more often go in the opposite direction
     // (forwards). According to benchmarks, merging forwards is slightly faster than merging
@@ -284,7 +335,7 @@ where
         // merge sort on short sequences, so this significantly improves performance.
         while start > 0 && end - start < MIN_RUN {
             start -= 1;
-            insert_head(&mut v[start..end], &mut is_less);
+            insert_head(&mut v[start..end], is_less);
         }
 
         // Compute the node power if there is a run on the stack
@@ -301,11 +352,12 @@ where
             let left = runs[r + 1];
             let right = runs[r];
             unsafe {
-                merge(
+                merge_bounded(
                     &mut v[left.start..right.start + right.len],
                     left.len,
-                    buf.as_mut_ptr(),
-                    &mut is_less,
+                    buf,
+                    cap,
+                    is_less,
                 );
             }
             runs[r] = Run { start: left.start, len: left.len + right.len, power: right.power };
@@ -347,4 +399,21 @@ where
     T: Ord,
 {
     merge_sort(slice, |a, b| a.lt(b));
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod merge_sort_try_reserve_tests {
+    use super::merge_sort_try_reserve;
+    use crate::powersort_final::ScratchFallback;
+    use crate::sequences::generate_m_runs;
+
+    #[test]
+    fn merge_sort_try_reserve_test_1() {
+        let mut v = generate_m_runs(500, 50);
+        let mut sorted = v.clone();
+        sorted.sort();
+
+        assert_eq!(merge_sort_try_reserve(&mut v, |a, b| a < b, ScratchFallback::InPlace), Ok(()));
+        assert_eq!(v, sorted);
+    }
+}
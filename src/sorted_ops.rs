@@ -0,0 +1,249 @@
+//! Set operations over sorted slices, with multiset semantics.
+//!
+//! If a value appears `m` times in `a` and `n` times in `b`, it appears `max(m, n)` times in their
+//! union, `min(m, n)` times in their intersection, `m - n` times in their difference (if positive)
+//! and `|m - n|` times in their symmetric difference. Equal elements are taken from `a` first.
+//!
+//! Every operation is a linear merge returning a [SortedOp] iterator, which can also be collected
+//! with [into_vec](SortedOp::into_vec). Blocks of elements that are not part of the result are
+//! skipped by galloping, so intersecting a short slice with a very long one only takes
+//! *O*(*m* \* log(*n* / *m*)) comparisons.
+
+use std::iter::FusedIterator;
+
+use crate::merge::gallop_from_start;
+
+/// The set operations done by [SortedOp].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+    SymmetricDifference,
+}
+
+impl Operation {
+    /// Whether elements of `a` without an equal element left in `b` are part of the result.
+    fn keeps_a(self) -> bool {
+        self != Operation::Intersection
+    }
+
+    /// Whether elements of `b` without an equal element left in `a` are part of the result.
+    fn keeps_b(self) -> bool {
+        matches!(self, Operation::Union | Operation::SymmetricDifference)
+    }
+
+    /// Whether a pair of equal elements is part of the result, as a single element.
+    fn keeps_pairs(self) -> bool {
+        matches!(self, Operation::Union | Operation::Intersection)
+    }
+}
+
+/// An iterator over the result of a set operation on two sorted slices, in sorted order.
+///
+/// Created by [union], [intersection], [difference] and [symmetric_difference].
+pub struct SortedOp<'a, T, F> {
+    a: &'a [T],
+    b: &'a [T],
+    is_less: F,
+    operation: Operation,
+}
+
+impl<'a, T, F> SortedOp<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    fn new(a: &'a [T], b: &'a [T], is_less: F, operation: Operation) -> Self {
+        SortedOp { a, b, is_less, operation }
+    }
+
+    /// Collects the result into a new vector.
+    pub fn into_vec(self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let mut v = Vec::with_capacity(self.size_hint().1.unwrap_or(0));
+        v.extend(self.cloned());
+        v
+    }
+}
+
+impl<'a, T, F> Iterator for SortedOp<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let operation = self.operation;
+        let is_less = &mut self.is_less;
+
+        loop {
+            let (a, b) = (self.a, self.b);
+            match (a.first(), b.first()) {
+                (None, None) => return None,
+                (Some(x), None) => {
+                    if !operation.keeps_a() {
+                        self.a = &[];
+                        return None;
+                    }
+                    self.a = &a[1..];
+                    return Some(x);
+                }
+                (None, Some(y)) => {
+                    if !operation.keeps_b() {
+                        self.b = &[];
+                        return None;
+                    }
+                    self.b = &b[1..];
+                    return Some(y);
+                }
+                (Some(x), Some(y)) => {
+                    if is_less(x, y) {
+                        if operation.keeps_a() {
+                            self.a = &a[1..];
+                            return Some(x);
+                        }
+                        // Skip every element of `a` less than `y`.
+                        self.a = &a[gallop_from_start(a, |z| is_less(z, y))..];
+                    } else if is_less(y, x) {
+                        if operation.keeps_b() {
+                            self.b = &b[1..];
+                            return Some(y);
+                        }
+                        // Skip every element of `b` less than `x`.
+                        self.b = &b[gallop_from_start(b, |z| is_less(z, x))..];
+                    } else {
+                        self.a = &a[1..];
+                        self.b = &b[1..];
+                        if operation.keeps_pairs() {
+                            return Some(x);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (na, nb) = (self.a.len(), self.b.len());
+        match self.operation {
+            Operation::Union => (na.max(nb), Some(na + nb)),
+            Operation::Intersection => (0, Some(na.min(nb))),
+            Operation::Difference => (na.saturating_sub(nb), Some(na)),
+            Operation::SymmetricDifference => (0, Some(na + nb)),
+        }
+    }
+}
+
+impl<T, F> FusedIterator for SortedOp<'_, T, F> where F: FnMut(&T, &T) -> bool {}
+
+/// Returns the multiset union of the sorted slices `a` and `b`.
+pub fn union<'a, T, F>(a: &'a [T], b: &'a [T], is_less: F) -> SortedOp<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    SortedOp::new(a, b, is_less, Operation::Union)
+}
+
+/// Returns the multiset intersection of the sorted slices `a` and `b`.
+pub fn intersection<'a, T, F>(a: &'a [T], b: &'a [T], is_less: F) -> SortedOp<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    SortedOp::new(a, b, is_less, Operation::Intersection)
+}
+
+/// Returns the multiset difference of the sorted slices `a` and `b`, the elements of `a` that are
+/// not in `b`.
+pub fn difference<'a, T, F>(a: &'a [T], b: &'a [T], is_less: F) -> SortedOp<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    SortedOp::new(a, b, is_less, Operation::Difference)
+}
+
+/// Returns the multiset symmetric difference of the sorted slices `a` and `b`, the elements that
+/// are in one of them but not in the other.
+pub fn symmetric_difference<'a, T, F>(a: &'a [T], b: &'a [T], is_less: F) -> SortedOp<'a, T, F>
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    SortedOp::new(a, b, is_less, Operation::SymmetricDifference)
+}
+
+#[cfg(test)]
+mod sorted_ops_tests {
+    use std::collections::BTreeMap;
+
+    use super::{difference, intersection, symmetric_difference, union};
+    use crate::sequences::generate_random_sequence_in_range;
+
+    fn sorted(length: usize, range: i32) -> Vec<i32> {
+        let mut v = generate_random_sequence_in_range(length, 0, range);
+        v.sort();
+        v
+    }
+
+    // Builds the expected result from the number of times each value appears in both slices.
+    fn expected(a: &[i32], b: &[i32], count: fn(usize, usize) -> usize) -> Vec<i32> {
+        let mut counts: BTreeMap<i32, (usize, usize)> = BTreeMap::new();
+        for &x in a {
+            counts.entry(x).or_default().0 += 1;
+        }
+        for &x in b {
+            counts.entry(x).or_default().1 += 1;
+        }
+        counts.into_iter().flat_map(|(x, (m, n))| std::iter::repeat_n(x, count(m, n))).collect()
+    }
+
+    #[test]
+    fn sorted_ops_test_1() {
+        let a = vec![1, 1, 1, 2, 4, 4, 6];
+        let b = vec![1, 2, 2, 4, 5];
+        let is_less = |x: &i32, y: &i32| x < y;
+
+        assert_eq!(union(&a, &b, is_less).into_vec(), vec![1, 1, 1, 2, 2, 4, 4, 5, 6]);
+        assert_eq!(intersection(&a, &b, is_less).into_vec(), vec![1, 2, 4]);
+        assert_eq!(difference(&a, &b, is_less).into_vec(), vec![1, 1, 4, 6]);
+        assert_eq!(symmetric_difference(&a, &b, is_less).into_vec(), vec![1, 1, 2, 4, 5, 6]);
+    }
+
+    #[test]
+    fn sorted_ops_test_2() {
+        for (na, nb, range) in [(500, 500, 100), (1000, 20, 300), (30, 700, 50), (0, 100, 10)] {
+            let (a, b) = (sorted(na, range), sorted(nb, range));
+            let is_less = |x: &i32, y: &i32| x < y;
+
+            assert!(union(&a, &b, is_less).copied().eq(expected(&a, &b, |m, n| m.max(n))));
+            assert!(intersection(&a, &b, is_less).copied().eq(expected(&a, &b, |m, n| m.min(n))));
+            assert!(difference(&a, &b, is_less).copied().eq(expected(&a, &b, |m, n| m.saturating_sub(n))));
+            assert!(symmetric_difference(&a, &b, is_less).copied().eq(expected(&a, &b, |m, n| m.abs_diff(n))));
+        }
+    }
+
+    #[test]
+    // Intersecting a few elements with a long slice skips most of it.
+    fn sorted_ops_test_3() {
+        let long: Vec<i32> = (0..100_000).collect();
+        let short = vec![5, 50_000, 99_999];
+
+        let mut comparisons = 0;
+        let result = intersection(&short, &long, |x, y| {
+            comparisons += 1;
+            x < y
+        })
+        .into_vec();
+        assert_eq!(result, short);
+        assert!(comparisons < 200);
+    }
+
+    #[test]
+    // Equal elements are taken from the first slice.
+    fn sorted_ops_test_4() {
+        let a = vec![(1, 'a'), (2, 'a')];
+        let b = vec![(1, 'b'), (1, 'b'), (3, 'b')];
+        let result = union(&a, &b, |x, y| x.0 < y.0).into_vec();
+        assert_eq!(result, vec![(1, 'a'), (1, 'b'), (2, 'a'), (3, 'b')]);
+    }
+}
@@ -0,0 +1,261 @@
+//! An ordered collection backed by sorted runs, merged lazily with the PowerSort rule.
+
+use std::mem::size_of;
+use std::ops::{Bound, Range, RangeBounds};
+use std::slice;
+
+use crate::lazy::{merge_sorted_iters, MergeSortedIters};
+use crate::powersort_final::{merge, power_sort, RunStack};
+
+// Inserted elements are buffered until there are this many, then become a run of their own.
+const BATCH_LENGTH: usize = 32;
+
+/// Merges the run `data[right]` into the run `data[left]` just before it.
+fn merge_runs<T: Ord>(data: &mut [T], left: &mut Range<usize>, right: &mut Range<usize>) {
+    if size_of::<T>() != 0 {
+        let mut buf = Vec::with_capacity(left.len().min(right.len()));
+        // Both runs are non-empty, and the buffer fits the shorter one.
+        let v = &mut data[left.start..right.end];
+        unsafe { merge(v, left.len(), buf.as_mut_ptr(), &mut |a: &T, b: &T| a < b) };
+    }
+    left.end = right.end;
+}
+
+/// An iterator over some elements of a [SortedVec], in sorted order.
+pub type SortedVecIter<'a, T> = MergeSortedIters<slice::Iter<'a, T>, fn(&&'a T, &&'a T) -> bool>;
+
+/// A sorted collection of elements, optimized for bulk inserts.
+///
+/// Elements are stored as a sequence of sorted runs, like the stack of pending runs of
+/// [power_sort]. Inserted elements are buffered in a short sorted run, and each full buffer or bulk
+/// insert is pushed as a new run, after merging the runs on top of the stack while the power rule
+/// says so. This spreads the cost of keeping the elements sorted over the inserts: every element
+/// takes part in *O*(log(*n*)) merges, and there are never more than *O*(log(*n*)) runs.
+///
+/// Queries look at every run: [contains](Self::contains) does a binary search in each of them,
+/// while [iter](Self::iter) and [range](Self::range) merge them lazily. [compact](Self::compact)
+/// merges everything into a single run, which makes queries as cheap as in a sorted slice.
+///
+/// Equal elements are kept in insertion order.
+#[derive(Clone, Debug)]
+pub struct SortedVec<T> {
+    /// The merged runs, one after the other.
+    data: Vec<T>,
+    /// The ranges of `data` holding each run, with powers computed for a power of two at least
+    /// `data.len()`.
+    runs: RunStack<Range<usize>>,
+    /// The sorted buffer of inserted elements.
+    pending: Vec<T>,
+}
+
+impl<T: Ord> SortedVec<T> {
+    pub fn new() -> Self {
+        SortedVec { data: Vec::new(), runs: RunStack::new(1), pending: Vec::new() }
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.data.len() + self.pending.len()
+    }
+
+    /// Returns true if there are no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of sorted runs the elements are split into, including the buffer.
+    pub fn run_count(&self) -> usize {
+        self.runs.iter().count() + usize::from(!self.pending.is_empty())
+    }
+
+    /// Inserts `x`, after any element equal to it.
+    pub fn insert(&mut self, x: T) {
+        let position = self.pending.partition_point(|y| *y <= x);
+        self.pending.insert(position, x);
+        if self.pending.len() >= BATCH_LENGTH {
+            self.flush();
+        }
+    }
+
+    /// Returns true if an element is equal to `x`.
+    pub fn contains(&self, x: &T) -> bool {
+        self.slices().any(|run| run.binary_search(x).is_ok())
+    }
+
+    /// Returns an iterator over all elements in sorted order.
+    pub fn iter(&self) -> SortedVecIter<'_, T> {
+        self.range(..)
+    }
+
+    /// Returns an iterator over the elements within `range`, in sorted order.
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> SortedVecIter<'_, T> {
+        let iters = self
+            .slices()
+            .map(|run| {
+                let start = match range.start_bound() {
+                    Bound::Included(x) => run.partition_point(|y| y < x),
+                    Bound::Excluded(x) => run.partition_point(|y| y <= x),
+                    Bound::Unbounded => 0,
+                };
+                let end = match range.end_bound() {
+                    Bound::Included(x) => run.partition_point(|y| y <= x),
+                    Bound::Excluded(x) => run.partition_point(|y| y < x),
+                    Bound::Unbounded => run.len(),
+                };
+                let run = &run[start..end.max(start)];
+                (run.iter(), Some(run.len()))
+            })
+            .collect();
+
+        merge_sorted_iters(iters, |a, b| a < b)
+    }
+
+    /// Merges all elements into a single run.
+    pub fn compact(&mut self) {
+        self.flush();
+        let data = &mut self.data;
+        self.runs.collapse(|left, right| merge_runs(data, left, right));
+    }
+
+    /// Consumes the collection and returns its elements in sorted order.
+    pub fn into_vec(mut self) -> Vec<T> {
+        self.compact();
+        self.data
+    }
+
+    /// The runs, from the oldest to the newest one.
+    fn slices(&self) -> impl Iterator<Item = &[T]> {
+        let runs = self.runs.iter().map(|run| &self.data[run.clone()]);
+        runs.chain(Some(&self.pending[..]).filter(|run| !run.is_empty()))
+    }
+
+    /// Pushes the buffer as a new run.
+    fn flush(&mut self) {
+        let mut pending = std::mem::take(&mut self.pending);
+        self.push_run(&mut pending);
+        // The buffer keeps its allocation.
+        self.pending = pending;
+    }
+
+    /// Pushes the sorted elements of `run` as a new run, leaving it empty.
+    fn push_run(&mut self, run: &mut Vec<T>) {
+        let (s2, n2) = (self.data.len(), run.len());
+        if n2 == 0 {
+            return;
+        }
+
+        self.runs.grow(s2 + n2);
+        self.data.append(run);
+
+        let data = &mut self.data;
+        self.runs.push(s2..s2 + n2, n2, |left, right| merge_runs(data, left, right));
+    }
+}
+
+impl<T: Ord> Default for SortedVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord> Extend<T> for SortedVec<T> {
+    /// Inserts all elements of `iter` at once, as a single new run.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut run: Vec<T> = iter.into_iter().collect();
+        power_sort(&mut run, |a, b| a < b);
+
+        // The buffer holds older elements, so it goes first.
+        self.flush();
+        self.push_run(&mut run);
+    }
+}
+
+impl<T: Ord> FromIterator<T> for SortedVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut v = SortedVec::new();
+        v.extend(iter);
+        v
+    }
+}
+
+#[cfg(test)]
+mod sorted_vec_tests {
+    use super::SortedVec;
+    use crate::sequences::generate_random_sequence_in_range;
+
+    #[test]
+    fn sorted_vec_test_1() {
+        let mut v = SortedVec::new();
+        let mut expected = Vec::new();
+
+        // Mix single inserts and bulk inserts of different sizes.
+        for (i, length) in [1, 50, 3, 700, 20, 2, 4000, 90, 5].into_iter().enumerate() {
+            let values = generate_random_sequence_in_range(length, 0, 1000);
+            expected.extend_from_slice(&values);
+            if i % 2 == 0 {
+                values.into_iter().for_each(|x| v.insert(x));
+            } else {
+                v.extend(values);
+            }
+
+            expected.sort();
+            assert_eq!(v.len(), expected.len());
+            assert!(v.iter().eq(expected.iter()));
+            assert!(v.run_count() <= 2 * usize::BITS as usize);
+        }
+
+        for x in [-1, 0, 17, 500, 999, 1000] {
+            assert_eq!(v.contains(&x), expected.contains(&x));
+        }
+        assert!(v.range(100..200).eq(expected.iter().filter(|x| (100..200).contains(*x))));
+        assert!(v.range(..=10).eq(expected.iter().filter(|x| **x <= 10)));
+        assert!(v.range(990..).eq(expected.iter().filter(|x| **x >= 990)));
+
+        v.compact();
+        assert_eq!(v.run_count(), 1);
+        assert_eq!(v.into_vec(), expected);
+    }
+
+    #[test]
+    // Many small bulk inserts keep few runs.
+    fn sorted_vec_test_2() {
+        let mut v: SortedVec<i32> = SortedVec::new();
+        for _ in 0..1000 {
+            v.extend(generate_random_sequence_in_range(10, 0, 100));
+            assert!(v.run_count() <= 16);
+        }
+        assert_eq!(v.len(), 10_000);
+        assert!(v.iter().is_sorted());
+    }
+
+    #[test]
+    // Equal elements are kept in insertion order.
+    fn sorted_vec_test_3() {
+        #[derive(Debug, Clone, Copy)]
+        struct Tagged(i32, usize);
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+        impl Eq for Tagged {}
+        impl PartialOrd for Tagged {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Tagged {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+
+        let values: Vec<Tagged> = generate_random_sequence_in_range(500, 0, 10).into_iter().zip(0..).map(|(k, i)| Tagged(k, i)).collect();
+        let mut v: SortedVec<Tagged> = values[..100].iter().copied().collect();
+        values[100..].iter().for_each(|&x| v.insert(x));
+
+        let mut expected = values.clone();
+        expected.sort();
+        assert!(v.iter().map(|x| x.1).eq(expected.iter().map(|x| x.1)));
+    }
+}
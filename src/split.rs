@@ -0,0 +1,450 @@
+//! Sorting sequences that are split across two slices, such as the two halves of a `VecDeque` or a
+//! buffer and its overflow.
+
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem::{self, size_of};
+use std::ops::Range;
+use std::{ptr, slice};
+
+use crate::powersort_final::{merge, RunStack, MIN_RUN_LENGTH};
+
+/// The concatenation of two mutable slices, indexed as one sequence.
+struct Split<'a, T> {
+    a: *mut T,
+    a_len: usize,
+    b: *mut T,
+    len: usize,
+    _marker: PhantomData<&'a mut [T]>,
+}
+
+impl<'a, T> Split<'a, T> {
+    fn new(a: &'a mut [T], b: &'a mut [T]) -> Self {
+        Split {
+            a: a.as_mut_ptr(),
+            a_len: a.len(),
+            b: b.as_mut_ptr(),
+            len: a.len() + b.len(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Pointer to the element at logical index `i`, which must be less than `len`.
+    fn ptr(&self, i: usize) -> *mut T {
+        debug_assert!(i < self.len);
+        unsafe {
+            if i < self.a_len {
+                self.a.add(i)
+            } else {
+                self.b.add(i - self.a_len)
+            }
+        }
+    }
+
+    fn get(&self, i: usize) -> &T {
+        unsafe { &*self.ptr(i) }
+    }
+
+    /// The range `start..end` as a single slice, if it doesn't cross from `a` into `b`.
+    fn contiguous(&mut self, start: usize, end: usize) -> Option<&mut [T]> {
+        if end <= self.a_len || start >= self.a_len {
+            Some(unsafe { slice::from_raw_parts_mut(self.ptr(start), end - start) })
+        } else {
+            None
+        }
+    }
+
+    fn reverse(&mut self, mut start: usize, mut end: usize) {
+        while start + 1 < end {
+            end -= 1;
+            unsafe { ptr::swap(self.ptr(start), self.ptr(end)) };
+            start += 1;
+        }
+    }
+
+    /// Copies `count` elements starting at logical index `start` into `dst`.
+    unsafe fn copy_out(&self, start: usize, count: usize, dst: *mut T) {
+        // Copy the part in `a` first, then the part in `b`.
+        let in_a = self.a_len.saturating_sub(start).min(count);
+        unsafe {
+            ptr::copy_nonoverlapping(self.a.add(start.min(self.a_len)), dst, in_a);
+            if count > in_a {
+                ptr::copy_nonoverlapping(self.ptr(start + in_a), dst.add(in_a), count - in_a);
+            }
+        }
+    }
+
+    /// Copies `count` elements from `src` into the sequence, starting at logical index `start`.
+    unsafe fn copy_in(&self, src: *const T, start: usize, count: usize) {
+        let in_a = self.a_len.saturating_sub(start).min(count);
+        unsafe {
+            ptr::copy_nonoverlapping(src, self.a.add(start.min(self.a_len)), in_a);
+            if count > in_a {
+                ptr::copy_nonoverlapping(src.add(in_a), self.ptr(start + in_a), count - in_a);
+            }
+        }
+    }
+}
+
+/// Like [extend_run_left](crate::powersort_final::extend_run_left), over a [Split].
+fn extend_run_left<T, F>(v: &Split<T>, start: usize, is_less: &mut F) -> (usize, bool)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    if start == 0 {
+        return (1, true);
+    }
+
+    let mut length = 1;
+    let mut i = start;
+    let is_increasing = !is_less(v.get(i), v.get(i - 1));
+
+    if is_increasing {
+        // Weakly increasing.
+        while i > 0 && !is_less(v.get(i), v.get(i - 1)) {
+            length += 1;
+            i -= 1;
+        }
+    } else {
+        // Strictly decreasing.
+        while i > 0 && is_less(v.get(i), v.get(i - 1)) {
+            length += 1;
+            i -= 1;
+        }
+    }
+
+    (length, is_increasing)
+}
+
+/// Inserts `v[start]` into the sorted range `v[start + 1..end]`.
+/// See [insert_head](crate::sort::insert_head).
+fn insert_head<T, F>(v: &mut Split<T>, start: usize, end: usize, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    if end - start >= 2 && is_less(v.get(start + 1), v.get(start)) {
+        unsafe {
+            let tmp = mem::ManuallyDrop::new(ptr::read(v.ptr(start)));
+
+            // If `is_less` panics, `hole` fills the remaining hole with `tmp`.
+            let mut hole = InsertionHole { src: &*tmp, dest: v.ptr(start + 1) };
+            ptr::copy_nonoverlapping(v.ptr(start + 1), v.ptr(start), 1);
+
+            for i in start + 2..end {
+                if !is_less(v.get(i), &*tmp) {
+                    break;
+                }
+                ptr::copy_nonoverlapping(v.ptr(i), v.ptr(i - 1), 1);
+                hole.dest = v.ptr(i);
+            }
+            // `hole` gets dropped and thus copies `tmp` into the remaining hole in `v`.
+        }
+    }
+
+    // When dropped, copies from `src` into `dest`.
+    struct InsertionHole<T> {
+        src: *const T,
+        dest: *mut T,
+    }
+
+    impl<T> Drop for InsertionHole<T> {
+        fn drop(&mut self) {
+            unsafe {
+                ptr::copy_nonoverlapping(self.src, self.dest, 1);
+            }
+        }
+    }
+}
+
+/// Merges the runs `v[start..mid]` and `v[mid..end]` using `buf` as temporary storage.
+/// See [merge](crate::powersort_final::merge), which is used directly if the runs don't cross from
+/// one slice to the other.
+///
+/// # Safety
+///
+/// Both runs must be non-empty, and `buf` must be long enough to hold a copy of the shorter run.
+unsafe fn merge_split<T, F>(
+    v: &mut Split<T>,
+    start: usize,
+    mid: usize,
+    end: usize,
+    buf: *mut T,
+    is_less: &mut F,
+) where
+    F: FnMut(&T, &T) -> bool,
+{
+    if let Some(run) = v.contiguous(start, end) {
+        unsafe { merge(run, mid - start, buf, is_less) };
+        return;
+    }
+
+    // Like `merge`, but `dest` and the unbuffered run are logical indices into `v`. If `is_less`
+    // panics, `hole` copies whatever is left in `buf` back into `v`.
+    let mut hole;
+
+    if mid - start <= end - mid {
+        // The left run is shorter.
+        unsafe {
+            v.copy_out(start, mid - start, buf);
+            hole = MergeHole { start: buf, end: buf.add(mid - start), dest: start, v };
+        }
+
+        let mut right = mid;
+        while hole.start < hole.end && right < end {
+            // Consume the lesser side.
+            // If equal, prefer the left run to maintain stability.
+            unsafe {
+                let to_copy = if is_less(hole.v.get(right), &*hole.start) {
+                    right += 1;
+                    hole.v.ptr(right - 1)
+                } else {
+                    hole.start = hole.start.add(1);
+                    hole.start.sub(1)
+                };
+                ptr::copy_nonoverlapping(to_copy, hole.v.ptr(hole.dest), 1);
+                hole.dest += 1;
+            }
+        }
+    } else {
+        // The right run is shorter.
+        unsafe {
+            v.copy_out(mid, end - mid, buf);
+            hole = MergeHole { start: buf, end: buf.add(end - mid), dest: mid, v };
+        }
+
+        // `hole.dest` is the end of what's left of the left run, `hole.end` of the right run.
+        let mut out = end;
+        while start < hole.dest && buf < hole.end {
+            // Consume the greater side.
+            // If equal, prefer the right run to maintain stability.
+            unsafe {
+                out -= 1;
+                let to_copy = if is_less(&*hole.end.sub(1), hole.v.get(hole.dest - 1)) {
+                    hole.dest -= 1;
+                    hole.v.ptr(hole.dest)
+                } else {
+                    hole.end = hole.end.sub(1);
+                    hole.end
+                };
+                ptr::copy_nonoverlapping(to_copy, hole.v.ptr(out), 1);
+            }
+        }
+    }
+    // Finally, `hole` gets dropped. If the shorter run was not fully consumed, whatever remains of
+    // it will now be copied into the hole in `v`.
+
+    // When dropped, copies the range `start..end` into `v` from the logical index `dest`.
+    struct MergeHole<'s, 'a, T> {
+        start: *mut T,
+        end: *mut T,
+        dest: usize,
+        v: &'s mut Split<'a, T>,
+    }
+
+    impl<T> Drop for MergeHole<'_, '_, T> {
+        fn drop(&mut self) {
+            // `T` is not a zero-sized type, and these are pointers into the buffer's elements.
+            unsafe {
+                let len = self.end.offset_from(self.start) as usize;
+                self.v.copy_in(self.start, self.dest, len);
+            }
+        }
+    }
+}
+
+/// Finds the run ending at `end`, makes it weakly increasing and extends it to the minimum run
+/// length. Returns the length of the run.
+fn find_run<T, F>(v: &mut Split<T>, end: usize, is_less: &mut F) -> usize
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (mut length, is_increasing) = extend_run_left(v, end, is_less);
+    // Reverse the run if it is decreasing so we only have (weakly) increasing runs.
+    if !is_increasing {
+        v.reverse(end + 1 - length, end + 1);
+    }
+
+    while length < MIN_RUN_LENGTH && end + 1 - length > 0 {
+        insert_head(v, end - length, end + 1, is_less);
+        length += 1;
+    }
+
+    length
+}
+
+/// Sorts the concatenation of the two slices in `v` with PowerSort, see
+/// [power_sort](crate::powersort_final::power_sort).
+fn power_sort_halves<T, F>(mut v: Split<T>, is_less: &mut F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let n = v.len;
+    if size_of::<T>() == 0 || n < 2 {
+        return;
+    }
+
+    // Stack for storing runs. Runs are found from the right, so a run pushed later lies to the left
+    // of the ones before it.
+    let mut runs: RunStack<Range<usize>> = RunStack::new(n);
+
+    // Buffer for merging runs
+    let mut scratch: Vec<T> = Vec::with_capacity(n / 2);
+    let buf = scratch.as_mut_ptr();
+
+    // Look for runs and merge if possible.
+    let mut end = n;
+    while end > 0 {
+        let length = find_run(&mut v, end - 1, is_less);
+        runs.push(end - length..end, length, |right, left| {
+            unsafe { merge_split(&mut v, left.start, left.end, right.end, buf, is_less) };
+            right.start = left.start;
+        });
+        end -= length;
+    }
+
+    // Merge remaining runs.
+    runs.finish(|right, left| {
+        unsafe { merge_split(&mut v, left.start, left.end, right.end, buf, is_less) };
+        right.start = left.start;
+    });
+}
+
+/// Sorts a `VecDeque` in place with PowerSort, without making it contiguous first.
+///
+/// The two halves of the ring buffer, as returned by `VecDeque::as_mut_slices`, are sorted as
+/// one sequence: runs are found across the wrap-around point, and merges that span it copy
+/// elements between the halves directly.
+pub fn power_sort_deque<T, F>(v: &mut VecDeque<T>, mut is_less: F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    let (a, b) = v.as_mut_slices();
+    power_sort_halves(Split::new(a, b), &mut is_less);
+}
+
+/// Sorts the concatenation of `a` and `b` in place with PowerSort, as if they were one slice.
+///
+/// When done, `a` holds the smallest `a.len()` elements and `b` the rest, both in sorted order.
+/// Runs may cross the boundary between the two slices, and merges move elements between them
+/// directly, so nothing is ever copied into a contiguous buffer of `a.len() + b.len()` elements.
+///
+/// The sort is stable, elements of `a` being considered to go before those of `b`.
+pub fn power_sort_split<T, F>(a: &mut [T], b: &mut [T], mut is_less: F)
+where
+    F: FnMut(&T, &T) -> bool,
+{
+    power_sort_halves(Split::new(a, b), &mut is_less);
+}
+
+#[cfg(test)]
+mod power_sort_deque_tests {
+    use std::collections::VecDeque;
+
+    use super::power_sort_deque;
+    use crate::sequences::generate_m_runs;
+
+    // Builds a deque holding `values` that wraps around the end of its buffer.
+    fn wrapped<T>(values: Vec<T>) -> VecDeque<T> {
+        let mut v = VecDeque::with_capacity(values.len());
+        let split = values.len() / 3;
+        let mut values = values.into_iter();
+        for x in values.by_ref().take(v.capacity() - split) {
+            v.push_back(x);
+        }
+        // Rotate the front of the buffer to its back so the contents wrap around.
+        for _ in 0..split {
+            let x = v.pop_front().unwrap();
+            v.push_back(x);
+        }
+        for x in values {
+            v.push_back(x);
+        }
+        v
+    }
+
+    #[test]
+    fn power_sort_deque_test_1() {
+        let values = generate_m_runs(1000, 40);
+        let mut v = wrapped(values);
+        assert!(!v.as_slices().1.is_empty());
+
+        let mut sorted: Vec<i32> = v.iter().copied().collect();
+        sorted.sort();
+
+        power_sort_deque(&mut v, |a, b| a < b);
+        assert!(v.iter().eq(sorted.iter()));
+    }
+
+    #[test]
+    // A single run wrapping around the end of the buffer.
+    fn power_sort_deque_test_2() {
+        let mut v = wrapped((0..100).rev().collect::<Vec<i32>>());
+        assert!(!v.as_slices().1.is_empty());
+
+        power_sort_deque(&mut v, |a, b| a < b);
+        assert!(v.iter().copied().eq(0..100));
+    }
+
+    #[test]
+    // Owned values must be moved between the halves without being duplicated or dropped.
+    fn power_sort_deque_test_3() {
+        let values: Vec<(String, usize)> =
+            generate_m_runs(500, 30).iter().map(|x| x.to_string()).zip(0..).collect();
+        let mut v = wrapped(values);
+        let mut sorted: Vec<(String, usize)> = v.iter().cloned().collect();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+        power_sort_deque(&mut v, |a, b| a.0 < b.0);
+        assert!(v.iter().eq(sorted.iter()));
+    }
+}
+
+#[cfg(test)]
+mod power_sort_split_tests {
+    use super::power_sort_split;
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    #[test]
+    fn power_sort_split_test_1() {
+        let values = generate_m_runs(1000, 40);
+        let mut sorted = values.clone();
+        sorted.sort();
+
+        for at in [0, 1, 300, 999, 1000] {
+            let (mut a, mut b) = (values[..at].to_vec(), values[at..].to_vec());
+            power_sort_split(&mut a, &mut b, |x, y| x < y);
+            assert_eq!(a, &sorted[..at]);
+            assert_eq!(b, &sorted[at..]);
+        }
+    }
+
+    #[test]
+    // A sorted prefix in one buffer followed by new data in the other, with equal keys keeping
+    // their order across the boundary.
+    fn power_sort_split_test_2() {
+        let mut prefix: Vec<(i32, usize)> =
+            generate_random_sequence_in_range(400, 0, 30).into_iter().zip(0..).collect();
+        prefix.sort_by_key(|x| x.0);
+        let overflow: Vec<(i32, usize)> =
+            generate_random_sequence_in_range(150, 0, 30).into_iter().zip(400..).collect();
+
+        let mut sorted: Vec<(i32, usize)> = prefix.iter().chain(overflow.iter()).copied().collect();
+        sorted.sort_by_key(|x| x.0);
+
+        let (mut a, mut b) = (prefix, overflow);
+        power_sort_split(&mut a, &mut b, |x, y| x.0 < y.0);
+        assert_eq!(a, &sorted[..400]);
+        assert_eq!(b, &sorted[400..]);
+    }
+
+    #[test]
+    // A decreasing run crossing the boundary.
+    fn power_sort_split_test_3() {
+        let mut a: Vec<String> = (50..100).rev().map(|x| x.to_string()).collect();
+        let mut b: Vec<String> = (10..50).rev().map(|x| x.to_string()).collect();
+        power_sort_split(&mut a, &mut b, |x, y| x < y);
+
+        assert!(a.iter().chain(b.iter()).map(|x| x.parse::<i32>().unwrap()).eq(10..100));
+        assert_eq!(a.len(), 50);
+    }
+}
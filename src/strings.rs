@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::permutation::apply_permutation;
+use crate::powersort_final::RunStack;
+
+/// Compares `a` and `b`, which are known to share their first `h` bytes.
+/// Returns the length of their longest common prefix, and their lexicographic ordering.
+fn compare_from<T: AsRef<[u8]>>(a: &T, b: &T, h: usize) -> (usize, Ordering) {
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let lcp = h + a[h..].iter().zip(&b[h..]).take_while(|(x, y)| x == y).count();
+
+    let ordering = match (a.get(lcp), b.get(lcp)) {
+        (Some(x), Some(y)) => x.cmp(y),
+        (x, y) => x.is_some().cmp(&y.is_some()),
+    };
+    (lcp, ordering)
+}
+
+/// Finds the run starting at `start` in the order given by `idx`, reverses it if it's strictly
+/// decreasing, and fills `lcp` for it. Returns the length of the run.
+fn find_run<T: AsRef<[u8]>>(v: &[T], idx: &mut [usize], lcp: &mut [usize], start: usize) -> usize {
+    let n = idx.len();
+    if start == n - 1 {
+        return 1;
+    }
+
+    let (h, ordering) = compare_from(&v[idx[start]], &v[idx[start + 1]], 0);
+    lcp[start + 1] = h;
+    let decreasing = ordering == Ordering::Greater;
+
+    let mut end = start + 2;
+    while end < n {
+        let (h, ordering) = compare_from(&v[idx[end - 1]], &v[idx[end]], 0);
+        if (ordering == Ordering::Greater) != decreasing {
+            break;
+        }
+        lcp[end] = h;
+        end += 1;
+    }
+
+    if decreasing {
+        // The lcp of two neighbours doesn't depend on their order, so reversing the run reverses
+        // the lcps between its elements.
+        idx[start..end].reverse();
+        lcp[start + 1..end].reverse();
+    }
+
+    end - start
+}
+
+/// Merges the runs `idx[start..mid]` and `idx[mid..end]` using their lcp arrays, and stores the
+/// merged run and its lcp array back into `idx[start..end]` and `lcp[start..end]`.
+///
+/// Every element carries the length of its common prefix with the last element written out. The
+/// element with the longer one is the smaller, so bytes are only compared when both are equal,
+/// and then only after that common prefix.
+fn lcp_merge<T: AsRef<[u8]>>(
+    v: &[T],
+    idx: &mut [usize],
+    lcp: &mut [usize],
+    (start, mid, end): (usize, usize, usize),
+    buf: &mut Vec<(usize, usize)>,
+) {
+    // Copy the left run out of the way, the output never overtakes the right run.
+    buf.clear();
+    buf.extend(idx[start..mid].iter().copied().zip(lcp[start..mid].iter().copied()));
+
+    let (mut i, mut j, mut k) = (0, mid, start);
+    // Common prefix of the next element of each run with the last element written out. There is
+    // none at the start, which is like having written out the empty string.
+    let (mut h_left, mut h_right) = (0, 0);
+
+    while i < buf.len() && j < end {
+        let take_left = match h_left.cmp(&h_right) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => {
+                let (h, ordering) = compare_from(&v[buf[i].0], &v[idx[j]], h_left);
+                // If equal, prefer the left run to maintain stability.
+                if ordering == Ordering::Greater {
+                    h_left = h;
+                    false
+                } else {
+                    h_right = h;
+                    true
+                }
+            }
+        };
+
+        if take_left {
+            idx[k] = buf[i].0;
+            lcp[k] = h_left;
+            i += 1;
+            if i < buf.len() {
+                h_left = buf[i].1;
+            }
+        } else {
+            idx[k] = idx[j];
+            lcp[k] = h_right;
+            j += 1;
+            if j < end {
+                h_right = lcp[j];
+            }
+        }
+        k += 1;
+    }
+
+    if i < buf.len() {
+        // The rest of the left run goes at the end, its first element now follows the last one
+        // written out.
+        for (offset, &(index, h)) in buf[i..].iter().enumerate() {
+            idx[k + offset] = index;
+            lcp[k + offset] = if offset == 0 { h_left } else { h };
+        }
+    } else if j < end {
+        // The rest of the right run is already in place.
+        lcp[j] = h_right;
+    }
+}
+
+/// Sorts byte strings, or anything that can be viewed as one such as `String` or `&str`, in
+/// lexicographic order using an LCP-aware PowerSort.
+///
+/// Alongside the runs, the length of the longest common prefix (LCP) of every element with its
+/// predecessor is kept. Merging uses these to skip the prefixes already known to be equal, so
+/// strings with long shared prefixes, like URLs or file paths, are compared much less often than
+/// with [power_sort](crate::powersort_final::power_sort). Runs are merged following
+/// [node_power](crate::powersort_final::node_power) too, but they are found from left to right and
+/// short runs aren't extended to a minimum length, so the merges may differ from the ones
+/// `power_sort` does on the same input.
+///
+/// The sort is stable. It sorts a permutation of `0..n` and then applies it to `v`, which needs
+/// *O*(*n*) extra memory.
+pub fn power_sort_strings<T: AsRef<[u8]>>(v: &mut [T]) {
+    let n = v.len();
+    if n < 2 {
+        return;
+    }
+
+    let mut idx: Vec<usize> = (0..n).collect();
+    let mut lcp: Vec<usize> = vec![0; n];
+    let mut buf = Vec::with_capacity(n / 2);
+
+    // Stack for storing runs, as ranges of `idx`.
+    let mut runs: RunStack<Range<usize>> = RunStack::new(n);
+
+    // Look for runs and merge if possible.
+    let mut s1 = 0;
+    while s1 < n {
+        let n1 = find_run(v, &mut idx, &mut lcp, s1);
+        runs.push(s1..s1 + n1, n1, |left, right| {
+            lcp_merge(v, &mut idx, &mut lcp, (left.start, right.start, right.end), &mut buf);
+            left.end = right.end;
+        });
+        s1 += n1;
+    }
+
+    // Merge remaining runs.
+    runs.finish(|left, right| {
+        lcp_merge(v, &mut idx, &mut lcp, (left.start, right.start, right.end), &mut buf);
+        left.end = right.end;
+    });
+
+    apply_permutation(v, &mut idx);
+}
+
+#[cfg(test)]
+mod power_sort_strings_tests {
+    use super::power_sort_strings;
+    use crate::sequences::{generate_paths, generate_random_sequence_in_range, generate_urls};
+
+    #[test]
+    fn power_sort_strings_test_1() {
+        let mut v = vec!["banana", "apple", "band", "ban", "", "apple", "b"];
+        power_sort_strings(&mut v);
+        assert_eq!(v, vec!["", "apple", "apple", "b", "ban", "banana", "band"]);
+    }
+
+    #[test]
+    fn power_sort_strings_test_2() {
+        for mut v in [generate_urls(2000), generate_paths(2000)] {
+            let mut sorted = v.clone();
+            sorted.sort();
+
+            power_sort_strings(&mut v);
+            assert_eq!(v, sorted);
+        }
+    }
+
+    #[test]
+    // Strings over a tiny alphabet have many duplicates and long shared prefixes.
+    fn power_sort_strings_test_3() {
+        let digits = generate_random_sequence_in_range(3000, 0, 2);
+        let mut v: Vec<Vec<u8>> = digits.chunks(3).map(|c| c.iter().map(|&d| b'a' + d as u8).collect()).collect();
+        let mut sorted = v.clone();
+        sorted.sort();
+
+        power_sort_strings(&mut v);
+        assert_eq!(v, sorted);
+    }
+
+    #[test]
+    // Equal strings keep their order.
+    fn power_sort_strings_test_4() {
+        struct Tagged(&'static str, usize);
+        impl AsRef<[u8]> for Tagged {
+            fn as_ref(&self) -> &[u8] {
+                self.0.as_bytes()
+            }
+        }
+
+        let words = ["b", "a", "b", "a", "ab", "b", "a"];
+        let mut v: Vec<Tagged> = words.iter().zip(0..).map(|(w, i)| Tagged(w, i)).collect();
+        power_sort_strings(&mut v);
+
+        let result: Vec<(&str, usize)> = v.iter().map(|t| (t.0, t.1)).collect();
+        assert_eq!(result, vec![("a", 1), ("a", 3), ("a", 6), ("ab", 4), ("b", 0), ("b", 2), ("b", 5)]);
+    }
+}
@@ -0,0 +1,483 @@
+//! Sorting anything with random access to its elements through the [SortTarget] trait, such as
+//! record stores, chunked vectors or columnar tables.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use crate::powersort_final::{RunStack, MAX_INSERTION, MIN_RUN_LENGTH};
+
+/// A sequence that can be sorted with [power_sort_target].
+///
+/// Elements are addressed by their position in `0..len()`. Runs are merged in place with swaps, in
+/// *O*(*n* \* log(*n*)) time per merge. Targets that can set some elements aside implement
+/// [BufferedSortTarget] too, which makes merging much faster.
+pub trait SortTarget {
+    /// Returns the number of elements.
+    fn len(&self) -> usize;
+
+    /// Returns true if there are no elements.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Compares the elements at positions `i` and `j`.
+    fn compare(&self, i: usize, j: usize) -> Ordering;
+
+    /// Returns true if the element at position `i` is less than the element at position `j`. This
+    /// is all the sort asks of positions, targets may override it when it's cheaper than
+    /// [compare](SortTarget::compare).
+    fn is_less(&self, i: usize, j: usize) -> bool {
+        self.compare(i, j) == Ordering::Less
+    }
+
+    /// Swaps the elements at positions `i` and `j`. With a [BufferedSortTarget], one of them may be
+    /// vacant.
+    fn swap(&mut self, i: usize, j: usize);
+}
+
+/// A [SortTarget] with a buffer of [buffer_len](BufferedSortTarget::buffer_len) slots, sorted with
+/// [power_sort_target_buffered].
+///
+/// [take](BufferedSortTarget::take) moves an element into a slot, leaving its position vacant, and
+/// [put](BufferedSortTarget::put) moves it back into a vacant position. A vacant position is only
+/// ever filled by `put`, or by swapping an element into it.
+pub trait BufferedSortTarget: SortTarget {
+    /// Returns the number of buffer slots.
+    fn buffer_len(&self) -> usize;
+
+    /// Moves the element at position `i` into buffer slot `slot`.
+    fn take(&mut self, i: usize, slot: usize);
+
+    /// Moves the element in buffer slot `slot` into the vacant position `i`.
+    fn put(&mut self, slot: usize, i: usize);
+
+    /// Compares the element in buffer slot `slot` with the element at position `j`.
+    fn compare_buffered(&self, slot: usize, j: usize) -> Ordering;
+}
+
+/// A [SortTarget] seen as a [BufferedSortTarget] with no buffer slots, so the merges never use
+/// them.
+struct Unbuffered<'a, S: ?Sized>(&'a mut S);
+
+impl<S: SortTarget + ?Sized> SortTarget for Unbuffered<'_, S> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn compare(&self, i: usize, j: usize) -> Ordering {
+        self.0.compare(i, j)
+    }
+
+    fn is_less(&self, i: usize, j: usize) -> bool {
+        self.0.is_less(i, j)
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        self.0.swap(i, j)
+    }
+}
+
+impl<S: SortTarget + ?Sized> BufferedSortTarget for Unbuffered<'_, S> {
+    fn buffer_len(&self) -> usize {
+        0
+    }
+
+    fn take(&mut self, _i: usize, _slot: usize) {
+        unreachable!("merges only use the buffer up to its length")
+    }
+
+    fn put(&mut self, _slot: usize, _i: usize) {
+        unreachable!("merges only use the buffer up to its length")
+    }
+
+    fn compare_buffered(&self, _slot: usize, _j: usize) -> Ordering {
+        unreachable!("merges only use the buffer up to its length")
+    }
+}
+
+fn is_less<S: SortTarget + ?Sized>(v: &S, i: usize, j: usize) -> bool {
+    v.is_less(i, j)
+}
+
+/// Reverses the elements in `start..end`.
+fn reverse<S: SortTarget + ?Sized>(v: &mut S, start: usize, end: usize) {
+    let (mut i, mut j) = (start, end);
+    while j > i + 1 {
+        j -= 1;
+        v.swap(i, j);
+        i += 1;
+    }
+}
+
+/// Inserts the element at `start` into the sorted elements in `start + 1..=end`.
+fn insert_head<S: SortTarget + ?Sized>(v: &mut S, start: usize, end: usize) {
+    let mut i = start;
+    while i < end && is_less(v, i + 1, i) {
+        v.swap(i, i + 1);
+        i += 1;
+    }
+}
+
+/// Returns the length of the run ending at `end`, and whether it is weakly increasing.
+/// See [extend_run_left](crate::powersort_final::extend_run_left).
+fn extend_run_left<S: SortTarget + ?Sized>(v: &S, end: usize) -> (usize, bool) {
+    if end == 0 {
+        return (1, true);
+    }
+
+    let is_increasing = !is_less(v, end, end - 1);
+    let mut i = end;
+    while i > 0 && is_less(v, i, i - 1) != is_increasing {
+        i -= 1;
+    }
+
+    (end - i + 1, is_increasing)
+}
+
+/// Finds the run ending at `end`, makes it weakly increasing and extends it to the minimum run
+/// length using insertion sort. Returns the length of the run.
+fn find_run<S: SortTarget + ?Sized>(v: &mut S, end: usize) -> usize {
+    let (mut length, is_increasing) = extend_run_left(v, end);
+    // Reverse the run if it is decreasing so we only have (weakly) increasing runs.
+    if !is_increasing {
+        reverse(v, end + 1 - length, end + 1);
+    }
+
+    while length < MIN_RUN_LENGTH && end + 1 > length {
+        insert_head(v, end - length, end);
+        length += 1;
+    }
+
+    length
+}
+
+/// Returns the first position in `start..end` for which `pred` is false, `pred` must be true for
+/// a prefix of the range and false for the rest.
+fn partition_point<S, P>(v: &S, start: usize, end: usize, mut pred: P) -> usize
+where
+    S: SortTarget + ?Sized,
+    P: FnMut(&S, usize) -> bool,
+{
+    let (mut lo, mut hi) = (start, end);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(v, mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+/// Buffered elements on their way back into the target. When dropped, the elements still in slots
+/// `start..end` are put into the vacant positions starting at `dest`.
+///
+/// Like `MergeHole` in [merge](crate::powersort_final::merge), this makes sure no element is lost
+/// if a comparison panics.
+struct Hole<'a, S: BufferedSortTarget + ?Sized> {
+    v: &'a mut S,
+    start: usize,
+    end: usize,
+    dest: usize,
+}
+
+impl<S: BufferedSortTarget + ?Sized> Drop for Hole<'_, S> {
+    fn drop(&mut self) {
+        for slot in self.start..self.end {
+            self.v.put(slot, self.dest + (slot - self.start));
+        }
+    }
+}
+
+/// Merges the runs in `start..mid` and `mid..end`, the left one being moved into the buffer.
+fn merge_lo<S: BufferedSortTarget + ?Sized>(v: &mut S, start: usize, mid: usize, end: usize) {
+    for slot in 0..mid - start {
+        v.take(start + slot, slot);
+    }
+
+    // The vacant positions are always right before the next element of the right run.
+    let mut hole = Hole { v, start: 0, end: mid - start, dest: start };
+    let mut right = mid;
+
+    while hole.start < hole.end && right < end {
+        // If equal, prefer the left run to maintain stability.
+        if hole.v.compare_buffered(hole.start, right) == Ordering::Greater {
+            hole.v.swap(hole.dest, right);
+            right += 1;
+        } else {
+            hole.v.put(hole.start, hole.dest);
+            hole.start += 1;
+        }
+        hole.dest += 1;
+    }
+
+    // Dropping the hole puts the rest of the left run in place.
+}
+
+/// Merges the runs in `start..mid` and `mid..end` from the back, the right one being moved into
+/// the buffer.
+fn merge_hi<S: BufferedSortTarget + ?Sized>(v: &mut S, start: usize, mid: usize, end: usize) {
+    for slot in 0..end - mid {
+        v.take(mid + slot, slot);
+    }
+
+    // The vacant positions are always right after the last remaining element of the left run.
+    let mut hole = Hole { v, start: 0, end: end - mid, dest: mid };
+
+    while hole.start < hole.end && hole.dest > start {
+        let last = hole.dest + (hole.end - hole.start) - 1;
+        // If equal, prefer the right run to maintain stability.
+        if hole.v.compare_buffered(hole.end - 1, hole.dest - 1) == Ordering::Less {
+            hole.v.swap(hole.dest - 1, last);
+            hole.dest -= 1;
+        } else {
+            hole.v.put(hole.end - 1, last);
+            hole.end -= 1;
+        }
+    }
+
+    // Dropping the hole puts the rest of the right run in place.
+}
+
+/// Merges the runs in `start..mid` and `mid..end`.
+///
+/// The shorter run is moved into the buffer if it fits. Otherwise the merge is split in two
+/// smaller ones by rotating the runs, like [merge_bounded](crate::powersort_final::merge_bounded).
+fn merge<S: BufferedSortTarget + ?Sized>(v: &mut S, start: usize, mid: usize, end: usize) {
+    let (n1, n2) = (mid - start, end - mid);
+    if n1 == 0 || n2 == 0 {
+        return;
+    }
+
+    let cap = v.buffer_len();
+    if n1 <= n2 && n1 <= cap {
+        merge_lo(v, start, mid, end);
+        return;
+    }
+    if n2 <= cap {
+        merge_hi(v, start, mid, end);
+        return;
+    }
+
+    if n1 + n2 == 2 {
+        if is_less(v, mid, start) {
+            v.swap(start, mid);
+        }
+        return;
+    }
+
+    let (left_cut, right_cut) = if n1 >= n2 {
+        let left_cut = start + n1 / 2;
+        // Elements of the right run strictly less than the cut element must end up before it.
+        (left_cut, partition_point(v, mid, end, |v, x| is_less(v, x, left_cut)))
+    } else {
+        let right_cut = mid + n2 / 2;
+        // Elements of the left run not greater than the cut element must stay before it.
+        (partition_point(v, start, mid, |v, x| !is_less(v, right_cut, x)), right_cut)
+    };
+
+    // Rotate the two inner pieces by reversing them.
+    reverse(v, left_cut, mid);
+    reverse(v, mid, right_cut);
+    reverse(v, left_cut, right_cut);
+
+    let new_mid = left_cut + (right_cut - mid);
+    merge(v, start, left_cut, new_mid);
+    merge(v, new_mid, right_cut, end);
+}
+
+/// Sorts any [SortTarget] with PowerSort, merging runs in place.
+///
+/// This works like [power_sort](crate::powersort_final::power_sort): runs are found from the
+/// right, extended to the minimum run length by insertion sort and merged in
+/// [node_power](crate::powersort_final::node_power) order. Elements are only accessed through the
+/// trait, so the same code sorts a slice, a chunked vector or a table whose columns must be kept in
+/// sync.
+///
+/// The sort is stable.
+pub fn power_sort_target<S: SortTarget + ?Sized>(v: &mut S) {
+    power_sort_target_buffered(&mut Unbuffered(v));
+}
+
+/// Like [power_sort_target], but merges runs through the buffer of `v` when the shorter one fits.
+pub fn power_sort_target_buffered<S: BufferedSortTarget + ?Sized>(v: &mut S) {
+    let n = v.len();
+    if n < 2 {
+        return;
+    }
+
+    // Use insertion sort for small sequences as it is faster.
+    if n < MAX_INSERTION {
+        for i in (0..n - 1).rev() {
+            insert_head(v, i, n - 1);
+        }
+        return;
+    }
+
+    // Stack for storing runs. Runs are found from the right, so a run pushed later lies to the left
+    // of the ones before it.
+    let mut runs: RunStack<Range<usize>> = RunStack::new(n);
+
+    // Look for runs and merge if possible.
+    let mut end = n;
+    while end > 0 {
+        let length = find_run(v, end - 1);
+        runs.push(end - length..end, length, |right, left| {
+            merge(v, left.start, left.end, right.end);
+            right.start = left.start;
+        });
+        end -= length;
+    }
+
+    // Merge remaining runs.
+    runs.finish(|right, left| {
+        merge(v, left.start, left.end, right.end);
+        right.start = left.start;
+    });
+}
+
+#[cfg(test)]
+mod power_sort_target_tests {
+    use std::cmp::Ordering;
+    use std::mem;
+
+    use super::{power_sort_target, power_sort_target_buffered, BufferedSortTarget, SortTarget};
+    use crate::sequences::{generate_m_runs, generate_random_sequence_in_range};
+
+    // A vector split into fixed size chunks, with a buffer of a given number of slots.
+    struct Chunked {
+        chunks: Vec<Vec<(i32, usize)>>,
+        chunk_size: usize,
+        len: usize,
+        buffer: Vec<(i32, usize)>,
+    }
+
+    impl Chunked {
+        fn new(values: &[(i32, usize)], chunk_size: usize, buffer_len: usize) -> Self {
+            Chunked {
+                chunks: values.chunks(chunk_size).map(|c| c.to_vec()).collect(),
+                chunk_size,
+                len: values.len(),
+                buffer: vec![(0, 0); buffer_len],
+            }
+        }
+
+        fn get(&self, i: usize) -> &(i32, usize) {
+            &self.chunks[i / self.chunk_size][i % self.chunk_size]
+        }
+
+        fn get_mut(&mut self, i: usize) -> &mut (i32, usize) {
+            &mut self.chunks[i / self.chunk_size][i % self.chunk_size]
+        }
+
+        fn to_vec(&self) -> Vec<(i32, usize)> {
+            self.chunks.concat()
+        }
+    }
+
+    impl SortTarget for Chunked {
+        fn len(&self) -> usize {
+            self.len
+        }
+
+        fn compare(&self, i: usize, j: usize) -> Ordering {
+            self.get(i).0.cmp(&self.get(j).0)
+        }
+
+        fn swap(&mut self, i: usize, j: usize) {
+            let x = mem::take(self.get_mut(i));
+            *self.get_mut(i) = mem::replace(self.get_mut(j), x);
+        }
+    }
+
+    impl BufferedSortTarget for Chunked {
+        fn buffer_len(&self) -> usize {
+            self.buffer.len()
+        }
+
+        fn take(&mut self, i: usize, slot: usize) {
+            self.buffer[slot] = mem::take(self.get_mut(i));
+        }
+
+        fn put(&mut self, slot: usize, i: usize) {
+            *self.get_mut(i) = mem::take(&mut self.buffer[slot]);
+        }
+
+        fn compare_buffered(&self, slot: usize, j: usize) -> Ordering {
+            self.buffer[slot].0.cmp(&self.get(j).0)
+        }
+    }
+
+    // Two columns sorted by the first one, without a buffer.
+    struct Table {
+        keys: Vec<i32>,
+        ids: Vec<usize>,
+    }
+
+    impl SortTarget for Table {
+        fn len(&self) -> usize {
+            self.keys.len()
+        }
+
+        fn compare(&self, i: usize, j: usize) -> Ordering {
+            self.keys[i].cmp(&self.keys[j])
+        }
+
+        fn swap(&mut self, i: usize, j: usize) {
+            self.keys.swap(i, j);
+            self.ids.swap(i, j);
+        }
+    }
+
+    fn tagged(keys: Vec<i32>) -> Vec<(i32, usize)> {
+        keys.into_iter().zip(0..).collect()
+    }
+
+    #[test]
+    fn power_sort_target_test_1() {
+        let values = tagged(generate_m_runs(1000, 30));
+        let mut sorted = values.clone();
+        sorted.sort_by_key(|x| x.0);
+
+        // A full buffer, a small one that forces split merges, and none at all.
+        for buffer_len in [500, 16, 0] {
+            let mut v = Chunked::new(&values, 64, buffer_len);
+            power_sort_target_buffered(&mut v);
+            assert_eq!(v.to_vec(), sorted);
+        }
+
+        // The same target, ignoring its buffer.
+        let mut v = Chunked::new(&values, 64, 500);
+        power_sort_target(&mut v);
+        assert_eq!(v.to_vec(), sorted);
+    }
+
+    #[test]
+    // Equal keys keep their order.
+    fn power_sort_target_test_2() {
+        let values = tagged(generate_random_sequence_in_range(2000, 0, 25));
+        let mut sorted = values.clone();
+        sorted.sort_by_key(|x| x.0);
+
+        let mut v = Chunked::new(&values, 100, 1000);
+        power_sort_target_buffered(&mut v);
+        assert_eq!(v.to_vec(), sorted);
+    }
+
+    #[test]
+    fn power_sort_target_test_3() {
+        let keys = generate_random_sequence_in_range(700, 0, 50);
+        let mut sorted = tagged(keys.clone());
+        sorted.sort_by_key(|x| x.0);
+
+        let mut table = Table { keys, ids: (0..700).collect() };
+        power_sort_target(&mut table);
+        assert!(table.keys.iter().copied().zip(table.ids.iter().copied()).eq(sorted.into_iter()));
+
+        let mut small = Table { keys: vec![3, 1, 2, 1], ids: vec![0, 1, 2, 3] };
+        power_sort_target(&mut small);
+        assert_eq!(small.keys, vec![1, 1, 2, 3]);
+        assert_eq!(small.ids, vec![1, 3, 2, 0]);
+    }
+}